@@ -0,0 +1,322 @@
+//! `influencer bindings export`: global shortcut configuration for desktop
+//! environments, generated from the `[[binding]]` entries of the config file.
+//!
+//! Each binding becomes an `influencer request` or `influencer batch`
+//! invocation with the macro already expanded, so that the shortcut doesn't
+//! depend on the macro staying the same. Connection settings still come from
+//! the config file when the shortcut runs.
+
+use crate::config::{Binding, ConfigFile, Expanded};
+
+use anyhow::Context as _;
+use clap::{ValueEnum, builder::PossibleValue};
+use std::fmt::Write as _;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `bindsym` lines for the sway config.
+    Sway,
+    /// `bind` lines for the Hyprland config.
+    Hyprland,
+    /// A shell script creating KDE Plasma custom shortcuts.
+    Kde,
+    /// A shell script creating GNOME custom shortcuts with gsettings.
+    Gnome,
+}
+impl ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Sway, Self::Hyprland, Self::Kde, Self::Gnome]
+    }
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(PossibleValue::new(match self {
+            Self::Sway => "sway",
+            Self::Hyprland => "hyprland",
+            Self::Kde => "kde",
+            Self::Gnome => "gnome",
+        }))
+    }
+}
+
+/// A key combination, like `Super+Shift+M`.
+struct Keys {
+    super_: bool,
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    key: String,
+}
+impl Keys {
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        let mut keys = Self {
+            super_: false,
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key: String::new(),
+        };
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        let key = parts.pop().filter(|key| !key.is_empty());
+        keys.key = key.with_context(|| format!("No key in {text}"))?.to_owned();
+        for modifier in parts {
+            let flag = match modifier.to_ascii_lowercase().as_str() {
+                "super" | "meta" | "logo" | "mod4" => &mut keys.super_,
+                "ctrl" | "control" => &mut keys.ctrl,
+                "alt" | "mod1" => &mut keys.alt,
+                "shift" => &mut keys.shift,
+                _ => anyhow::bail!("Unknown modifier {modifier} in {text}"),
+            };
+            *flag = true;
+        }
+        Ok(keys)
+    }
+    /// The modifiers that are set, out of `names` (Super, Ctrl, Alt, Shift).
+    fn modifiers<'a>(&self, names: [&'a str; 4]) -> Vec<&'a str> {
+        let set = [self.super_, self.ctrl, self.alt, self.shift];
+        names
+            .into_iter()
+            .zip(set)
+            .filter_map(|(name, set)| set.then_some(name))
+            .collect()
+    }
+    /// Letters in the given case; other key names as they were written.
+    fn key(&self, uppercase: bool) -> String {
+        match (self.key.chars().count(), uppercase) {
+            (1, true) => self.key.to_uppercase(),
+            (1, false) => self.key.to_lowercase(),
+            _ => self.key.clone(),
+        }
+    }
+}
+
+struct Shortcut {
+    keys: Keys,
+    /// Shown by the desktop environment.
+    name: String,
+    command: Vec<String>,
+}
+
+/// Generates configuration for all bindings in `file`, invoking `program`.
+pub fn export(file: &ConfigFile, format: Format, program: &str) -> anyhow::Result<String> {
+    let shortcuts = file
+        .bindings
+        .iter()
+        .map(|binding| shortcut(file, binding, program))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let format_name = format.to_possible_value().unwrap();
+    let mut out = String::new();
+    if matches!(format, Format::Kde | Format::Gnome) {
+        out.push_str("#!/bin/sh\n");
+    }
+    writeln!(
+        out,
+        "# Generated by influencer bindings export --format {}",
+        format_name.get_name()
+    )?;
+    match format {
+        Format::Sway => sway(&mut out, &shortcuts)?,
+        Format::Hyprland => hyprland(&mut out, &shortcuts)?,
+        Format::Kde => kde(&mut out, &shortcuts)?,
+        Format::Gnome => gnome(&mut out, &shortcuts)?,
+    }
+    Ok(out)
+}
+
+fn shortcut(file: &ConfigFile, binding: &Binding, program: &str) -> anyhow::Result<Shortcut> {
+    let context = || format!("Binding {}", binding.keys);
+    let keys = Keys::parse(&binding.keys)?;
+    let args = binding.args();
+    let expanded = file
+        .macro_(&binding.macro_)
+        .and_then(|macro_| macro_.expand(&args))
+        .with_context(context)?;
+    let mut name = binding.macro_.clone();
+    for (param, value) in &args {
+        write!(name, " {param}={value}")?;
+    }
+    let mut command = vec![program.to_owned()];
+    if let Some(ref profile) = binding.profile {
+        command.extend(["--profile".to_owned(), profile.clone()]);
+    }
+    match expanded {
+        Expanded::Request { request_type, data } => {
+            command.extend(["request".to_owned(), request_type]);
+            command.extend(data.map(|data| data.to_string()));
+        }
+        Expanded::Batch {
+            requests,
+            halt_on_failure,
+            execution_type,
+        } => {
+            command.push("batch".to_owned());
+            if halt_on_failure == Some(true) {
+                command.push("--halt-on-failure".to_owned());
+            }
+            if let Some(execution_type) = execution_type {
+                command.extend(["--execution-type".to_owned(), execution_type.to_string()]);
+            }
+            command.push(serde_json::to_string(&requests)?);
+        }
+    }
+    Ok(Shortcut {
+        keys,
+        name,
+        command,
+    })
+}
+
+fn sway(out: &mut String, shortcuts: &[Shortcut]) -> std::fmt::Result {
+    for shortcut in shortcuts {
+        let mut combo = shortcut.keys.modifiers(["Mod4", "Ctrl", "Mod1", "Shift"]);
+        let key = shortcut.keys.key(false);
+        combo.push(&key);
+        writeln!(
+            out,
+            "bindsym {} exec {}",
+            combo.join("+"),
+            shell_command(&shortcut.command)
+        )?;
+    }
+    Ok(())
+}
+
+fn hyprland(out: &mut String, shortcuts: &[Shortcut]) -> std::fmt::Result {
+    for shortcut in shortcuts {
+        let modifiers = shortcut.keys.modifiers(["SUPER", "CTRL", "ALT", "SHIFT"]);
+        // Hyprland would take a single # for the start of a comment
+        let command = shell_command(&shortcut.command).replace('#', "##");
+        writeln!(
+            out,
+            "bind = {}, {}, exec, {command}",
+            modifiers.join(" "),
+            shortcut.keys.key(true)
+        )?;
+    }
+    Ok(())
+}
+
+fn kde(out: &mut String, shortcuts: &[Shortcut]) -> std::fmt::Result {
+    out.push_str("# Log out and back in afterwards for the shortcuts to take effect\n");
+    out.push_str("mkdir -p ~/.local/share/applications\n");
+    for (i, shortcut) in shortcuts.iter().enumerate() {
+        let id = format!("influencer-{}.desktop", i + 1);
+        let mut combo = shortcut.keys.modifiers(["Meta", "Ctrl", "Alt", "Shift"]);
+        let key = shortcut.keys.key(true);
+        combo.push(&key);
+        let combo = combo.join("+");
+        let exec: Vec<String> = shortcut.command.iter().map(|arg| exec_quote(arg)).collect();
+        writeln!(out)?;
+        writeln!(out, "cat > ~/.local/share/applications/{id} <<'EOF'")?;
+        writeln!(out, "[Desktop Entry]")?;
+        writeln!(out, "Type=Application")?;
+        writeln!(out, "Name={}", desktop_escape(&shortcut.name))?;
+        writeln!(out, "Exec={}", exec.join(" "))?;
+        writeln!(out, "NoDisplay=true")?;
+        writeln!(out, "X-KDE-Shortcuts={combo}")?;
+        writeln!(out, "EOF")?;
+        writeln!(
+            out,
+            "kwriteconfig6 --file kglobalshortcutsrc --group services --group {id} --key _launch {}",
+            shell_quote(&combo)
+        )?;
+    }
+    Ok(())
+}
+
+fn gnome(out: &mut String, shortcuts: &[Shortcut]) -> std::fmt::Result {
+    const SCHEMA: &str = "org.gnome.settings-daemon.plugins.media-keys";
+    const DIR: &str = "/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings";
+    // The list holds paths like '/org/.../custom0/', as GVariant strings
+    out.push_str("# Keeps the custom shortcuts that weren't set up by influencer\n");
+    writeln!(
+        out,
+        "others=$(gsettings get {SCHEMA} custom-keybindings \\\n  \
+         | sed -e 's/^@as //' -e 's/[][]//g' | tr ',' '\\n' \\\n  \
+         | sed -e 's/^ *//' -e '/\\/influencer[0-9]*\\/.$/d' -e '/^$/d' | paste -s -d , -)"
+    )?;
+    // The paths need no escaping inside double quotes
+    let paths: Vec<String> = (1..=shortcuts.len())
+        .map(|i| gvariant_quote(&format!("{DIR}/influencer{i}/")))
+        .collect();
+    writeln!(
+        out,
+        "gsettings set {SCHEMA} custom-keybindings \"[${{others:+$others, }}{}]\"",
+        paths.join(", ")
+    )?;
+    for (i, shortcut) in shortcuts.iter().enumerate() {
+        let mut combo = String::new();
+        for modifier in shortcut
+            .keys
+            .modifiers(["Super", "Control", "Alt", "Shift"])
+        {
+            write!(combo, "<{modifier}>")?;
+        }
+        combo.push_str(&shortcut.keys.key(false));
+        let path = format!("{SCHEMA}.custom-keybinding:{DIR}/influencer{}/", i + 1);
+        writeln!(out)?;
+        for (key, value) in [
+            ("name", shortcut.name.clone()),
+            ("command", shell_command(&shortcut.command)),
+            ("binding", combo),
+        ] {
+            writeln!(
+                out,
+                "gsettings set {path} {key} {}",
+                shell_quote(&gvariant_quote(&value))
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn shell_command(args: &[String]) -> String {
+    let args: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+    args.join(" ")
+}
+
+/// Quotes an argument for POSIX shells, unless it doesn't need quoting.
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=.,/:@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_owned()
+    } else if arg.contains('\'') && !arg.contains(['"', '\\', '$', '`', '!']) {
+        // Easier to read than closing and reopening single quotes
+        format!("\"{arg}\"")
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Quotes a string for GVariant text format, as gsettings takes it.
+fn gvariant_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+}
+
+/// Quotes an argument of a desktop entry's `Exec` key, escaping the result
+/// as a desktop entry string. Backslashes end up doubled twice; that's what
+/// the specification asks for.
+fn exec_quote(arg: &str) -> String {
+    let reserved = |c: char| " \t\n\"'\\><~|&;$*?#()`".contains(c);
+    let arg = match arg.is_empty() || arg.contains(reserved) {
+        true => {
+            let mut quoted = String::from('"');
+            for c in arg.chars() {
+                if matches!(c, '"' | '`' | '$' | '\\') {
+                    quoted.push('\\');
+                }
+                quoted.push(c);
+            }
+            quoted.push('"');
+            quoted
+        }
+        false => arg.to_owned(),
+    };
+    desktop_escape(&arg).replace('%', "%%")
+}
+
+/// Escapes a desktop entry string value.
+fn desktop_escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('\n', r"\n")
+        .replace('\t', r"\t")
+}
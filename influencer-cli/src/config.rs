@@ -0,0 +1,340 @@
+//! The config file (`~/.config/influencer/config.toml` by default), with
+//! connection profiles and macros.
+//!
+//! ```toml
+//! # Used if --profile isn't given
+//! default_profile = "home"
+//!
+//! [profiles.home]
+//! password = "p4ssw0rd"
+//!
+//! [profiles.studio]
+//! host = "wss://obs.studio.lan"
+//! ca_file = "/etc/influencer/studio-ca.pem"
+//!
+//! [macros.mute-mic]
+//! request = "SetInputMute"
+//! data = { inputName = "Mic", inputMuted = true }
+//!
+//! [macros.volume]
+//! request = "SetInputVolume"
+//! data = { inputName = "{input}", inputVolumeDb = "{db:json}" }
+//!
+//! [macros.scene]
+//! description = "Switch to a scene"
+//! request = "SetCurrentProgramScene"
+//! data = { sceneName = "{name}" }
+//! params = { name = "Game" }
+//!
+//! [macros.brb]
+//! batch = [
+//!     { requestType = "SetCurrentProgramScene", requestData = { sceneName = "BRB" } },
+//!     { requestType = "SetInputMute", requestData = { inputName = "Mic", inputMuted = true } },
+//! ]
+//!
+//! # For `influencer bindings export`
+//! [[binding]]
+//! keys = "Super+Shift+M"
+//! macro = "mute-mic"
+//!
+//! [[binding]]
+//! keys = "Super+F9"
+//! macro = "scene"
+//! params = { name = "BRB" }
+//! ```
+//!
+//! Macro parameters are given as `name=value`, and replace `{name}` in
+//! string values, so `name=1` is still the string `"1"`. A string consisting
+//! of nothing but `{name:json}` is replaced by the value parsed as JSON
+//! instead, so `db=-10` is a number there. `params` holds default values;
+//! parameters without one are required.
+
+use anyhow::Context as _;
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub macros: BTreeMap<String, Macro>,
+    #[serde(default, rename = "binding")]
+    pub bindings: Vec<Binding>,
+}
+impl ConfigFile {
+    /// `$XDG_CONFIG_HOME/influencer/config.toml` or the platform's equivalent.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("influencer").join("config.toml"))
+    }
+    /// Loads the config file at `path`. A missing file is only an error if
+    /// `required` is set.
+    pub fn load(path: &Path, required: bool) -> anyhow::Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if !required && error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default());
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        toml::from_str(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+    /// The profile called `name`, or the default profile if there is one.
+    pub fn profile(&self, name: Option<&str>) -> anyhow::Result<Option<&Profile>> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(Some(profile)),
+                None => anyhow::bail!("No profile named {name} in the config file"),
+            },
+            None => Ok(None),
+        }
+    }
+    pub fn macro_(&self, name: &str) -> anyhow::Result<&Macro> {
+        self.macros
+            .get(name)
+            .with_context(|| format!("No macro named {name} in the config file"))
+    }
+}
+
+/// Connection settings. Anything not set falls back to the built-in default.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub password: Option<String>,
+    pub tls: Option<bool>,
+    pub ca_file: Option<PathBuf>,
+    pub insecure: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Macro {
+    pub description: Option<String>,
+    pub request: Option<String>,
+    pub data: Option<Value>,
+    pub batch: Option<Vec<Value>>,
+    pub halt_on_failure: Option<bool>,
+    pub execution_type: Option<i32>,
+    /// Default parameter values.
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+/// A global shortcut running a macro.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Binding {
+    /// Modifiers and a key, like `Super+Shift+M`.
+    pub keys: String,
+    #[serde(rename = "macro")]
+    pub macro_: String,
+    /// Parameter values. Values other than strings are passed as JSON.
+    #[serde(default)]
+    pub params: BTreeMap<String, Value>,
+    /// Connection profile to use instead of the default one.
+    pub profile: Option<String>,
+}
+impl Binding {
+    /// The parameters as `name=value` pairs, as [`Macro::expand`] takes them.
+    pub fn args(&self) -> Vec<(String, String)> {
+        self.params
+            .iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => (name.clone(), value.clone()),
+                value => (name.clone(), value.to_string()),
+            })
+            .collect()
+    }
+}
+
+/// What a macro does, with its parameters filled in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expanded {
+    Request {
+        request_type: String,
+        data: Option<Value>,
+    },
+    Batch {
+        requests: Vec<Value>,
+        halt_on_failure: Option<bool>,
+        execution_type: Option<i32>,
+    },
+}
+
+impl Macro {
+    pub fn expand(&self, args: &[(String, String)]) -> anyhow::Result<Expanded> {
+        let params: HashMap<String, Value> = self
+            .params
+            .iter()
+            .chain(args.iter().map(|(name, value)| (name, value)))
+            .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+            .collect();
+        let mut used = Vec::new();
+        let expanded = match (&self.request, &self.batch) {
+            (Some(request_type), None) => Expanded::Request {
+                request_type: request_type.clone(),
+                data: match self.data {
+                    Some(ref data) => Some(substitute(data, &params, &mut used)?),
+                    None => None,
+                },
+            },
+            (None, Some(batch)) => Expanded::Batch {
+                requests: batch
+                    .iter()
+                    .map(|request| substitute(request, &params, &mut used))
+                    .collect::<anyhow::Result<_>>()?,
+                halt_on_failure: self.halt_on_failure,
+                execution_type: self.execution_type,
+            },
+            _ => anyhow::bail!("A macro needs either `request` or `batch`"),
+        };
+        if let Some((name, _)) = args.iter().find(|(name, _)| !used.contains(name)) {
+            anyhow::bail!("Unknown parameter {name}");
+        }
+        Ok(expanded)
+    }
+    /// The names of all parameters used by the macro, sorted.
+    pub fn param_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut visit = |value: &Value| collect_params(value, &mut names);
+        self.data.iter().for_each(&mut visit);
+        self.batch.iter().flatten().for_each(&mut visit);
+        names.sort();
+        names
+    }
+}
+
+/// Parses the text of a rules or steps file: YAML if `path` ends in `.yaml`
+/// or `.yml`, TOML otherwise.
+pub fn parse_file<T: DeserializeOwned>(path: &Path, text: &str) -> anyhow::Result<T> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml" | "yml") => Ok(serde_norway::from_str(text)?),
+        _ => Ok(toml::from_str(text)?),
+    }
+}
+
+/// Splits `name=value` arguments.
+pub fn parse_param(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((name, value)) => Ok((name.to_owned(), value.to_owned())),
+        None => Err(format!("expected NAME=VALUE, got {arg}")),
+    }
+}
+
+/// Replaces `{name}` placeholders in the strings of `value`, like macro
+/// parameters. A value filling a whole string keeps its type, so a captured
+/// number stays a number, and `{name:json}` parses a string value as JSON.
+/// Within longer strings, strings are inserted as they are, anything else
+/// as JSON.
+pub fn fill_placeholders(value: &Value, params: &HashMap<String, Value>) -> anyhow::Result<Value> {
+    substitute(value, params, &mut Vec::new())
+}
+
+fn substitute(
+    value: &Value,
+    params: &HashMap<String, Value>,
+    used: &mut Vec<String>,
+) -> anyhow::Result<Value> {
+    Ok(match value {
+        Value::String(text) => match next_placeholder(text) {
+            Some(("", name, json, "")) => match (param(params, used, name)?, json) {
+                (Value::String(value), true) => serde_json::from_str(value)
+                    .with_context(|| format!("Parameter {name} isn't valid JSON: {value}"))?,
+                (value, _) => value.clone(),
+            },
+            _ => {
+                let mut out = String::new();
+                let mut rest = text.as_str();
+                while let Some((before, name, _, after)) = next_placeholder(rest) {
+                    out.push_str(before);
+                    match param(params, used, name)? {
+                        Value::String(value) => out.push_str(value),
+                        value => out.push_str(&value.to_string()),
+                    }
+                    rest = after;
+                }
+                out.push_str(rest);
+                Value::String(out)
+            }
+        },
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| substitute(item, params, used))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), substitute(value, params, used)?)))
+                .collect::<anyhow::Result<_>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+fn param<'a>(
+    params: &'a HashMap<String, Value>,
+    used: &mut Vec<String>,
+    name: &str,
+) -> anyhow::Result<&'a Value> {
+    let value = params
+        .get(name)
+        .with_context(|| format!("Missing parameter {name}"))?;
+    used.push(name.to_owned());
+    Ok(value)
+}
+
+fn collect_params(value: &Value, names: &mut Vec<String>) {
+    match value {
+        Value::String(text) => {
+            let mut rest = text.as_str();
+            while let Some((_, name, _, after)) = next_placeholder(rest) {
+                if !names.iter().any(|v| v == name) {
+                    names.push(name.to_owned());
+                }
+                rest = after;
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| collect_params(item, names)),
+        Value::Object(fields) => fields
+            .values()
+            .for_each(|value| collect_params(value, names)),
+        _ => {}
+    }
+}
+
+/// Finds the first `{name}` or `{name:json}`, returning the text before
+/// it, the name, whether it's `:json`, and the text after it. Names consist
+/// of letters, digits, `_` and `-`.
+fn next_placeholder(text: &str) -> Option<(&str, &str, bool, &str)> {
+    let mut search = 0;
+    while let Some(start) = text[search..].find('{').map(|i| search + i) {
+        let rest = &text[start + 1..];
+        if let Some(end) = rest.find('}') {
+            let (name, json) = match rest[..end].strip_suffix(":json") {
+                Some(name) => (name, true),
+                None => (&rest[..end], false),
+            };
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            {
+                return Some((&text[..start], name, json, &rest[end + 1..]));
+            }
+        }
+        search = start + 1;
+    }
+    None
+}
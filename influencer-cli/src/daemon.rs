@@ -0,0 +1,196 @@
+//! `influencer daemon`: keeps one authenticated, auto-reconnecting
+//! connection to OBS and serves it on a Unix domain socket, so that
+//! `influencer request` and `influencer batch` don't have to connect and
+//! authenticate every time. If OBS isn't running yet, the daemon waits for
+//! it.
+//!
+//! The protocol on the socket is obs-websocket messages as JSON lines: the
+//! daemon first sends a line with the [`Target`] it is connected to, then
+//! each `Request` or `RequestBatch` message line is answered by the response
+//! message line.
+
+use crate::session::{self, Session};
+
+use anyhow::Context as _;
+use influencer::client;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead as _, BufReader, ErrorKind as IoErrorKind, Write as _},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tungstenite::Message as WsMessage;
+
+/// How long `request` and `batch` wait for the daemon to answer: longer
+/// than the daemon waits for OBS, so that it's the one to give up first.
+const CALL_TIMEOUT: Duration = session::RESPONSE_TIMEOUT.saturating_add(Duration::from_secs(5));
+/// How long to wait between attempts to connect to OBS when it isn't
+/// running.
+const CONNECT_RETRY: Duration = Duration::from_secs(1);
+/// How long an interrupted daemon waits for the connection to close.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Where a daemon is connected to.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Target {
+    /// The WebSocket URL, which has the host, port and whether it's TLS.
+    pub url: String,
+}
+impl Target {
+    pub fn of(config: &client::Config) -> Self {
+        Self { url: config.url() }
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/influencer.sock`, or a per-user file in the temporary
+/// directory if there is no runtime directory.
+pub fn default_socket_path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(dir) => dir.join("influencer.sock"),
+        None => {
+            // SAFETY: getuid can't fail and has no side effects
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("influencer-{uid}.sock"))
+        }
+    }
+}
+
+/// Binds `path` so that only the current user can connect, without a window
+/// in which the socket exists with looser permissions.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    // SAFETY: umask can't fail. Nothing else creates files meanwhile, as
+    // no other threads have been started yet.
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(path);
+    unsafe { libc::umask(umask) };
+    listener
+}
+
+/// Connects to OBS, waiting for it to start if it can't be reached. Other
+/// errors, like a wrong password, are returned.
+fn connect(config: client::Config) -> anyhow::Result<Session> {
+    let mut waiting = false;
+    loop {
+        match Session::connect(config.clone(), None) {
+            Ok(session) => return Ok(session),
+            Err(error)
+                if error
+                    .downcast_ref::<client::Error>()
+                    .is_some_and(client::Error::is_disconnect) =>
+            {
+                if !waiting {
+                    eprintln!("Waiting for OBS ({error:#})");
+                    waiting = true;
+                }
+                thread::sleep(CONNECT_RETRY);
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Serves requests on `path` until interrupted.
+pub fn run(mut config: client::Config, path: &Path) -> anyhow::Result<()> {
+    if UnixStream::connect(path).is_ok() {
+        anyhow::bail!("A daemon is already listening on {}", path.display());
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = std::fs::remove_file(path);
+    let listener =
+        bind_private(path).with_context(|| format!("Failed to listen on {}", path.display()))?;
+    config.auto_reconnect = true;
+    config.event_subscriptions = Some(0);
+    let target = Arc::new(serde_json::to_string(&Target::of(&config))?);
+    let session = match connect(config) {
+        Ok(session) => Arc::new(session),
+        Err(error) => {
+            let _ = std::fs::remove_file(path);
+            return Err(error);
+        }
+    };
+    let path_ = path.to_owned();
+    let session_ = session.clone();
+    ctrlc::set_handler(move || {
+        // Let OBS see an orderly disconnect
+        session_.start_close();
+        let start = Instant::now();
+        while !session_.is_finished() && start.elapsed() < CLOSE_TIMEOUT {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _ = std::fs::remove_file(&path_);
+        std::process::exit(130);
+    })?;
+    eprintln!("Listening on {}", path.display());
+    for stream in listener.incoming() {
+        // Only noticed on the next connection, which is good enough
+        if session.is_finished() {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let session = session.clone();
+        let target = target.clone();
+        thread::spawn(move || {
+            if let Err(error) = serve(&session, &target, stream) {
+                eprintln!("Client: {error:#}");
+            }
+        });
+    }
+    let _ = std::fs::remove_file(path);
+    match Arc::try_unwrap(session) {
+        Ok(session) => session.close(),
+        Err(_) => Ok(()),
+    }
+}
+
+fn serve(session: &Session, target: &str, stream: UnixStream) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{target}")?;
+    for line in BufReader::new(stream).lines() {
+        let response = session.call(WsMessage::text(line?))?;
+        writeln!(writer, "{}", response.to_text()?)?;
+    }
+    Ok(())
+}
+
+/// Sends a request or batch message through the daemon listening on `path`,
+/// and waits for the response. Returns `None` if no daemon is listening, or
+/// if `target` is given and the daemon is connected somewhere else.
+pub fn call(
+    path: &Path,
+    message: &WsMessage,
+    target: Option<&Target>,
+) -> anyhow::Result<Option<WsMessage>> {
+    let Ok(stream) = UnixStream::connect(path) else {
+        return Ok(None);
+    };
+    stream.set_read_timeout(Some(CALL_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let daemon_target: Target = serde_json::from_str(&read_line(&mut reader)?)
+        .context("Unexpected greeting from the daemon")?;
+    if target.is_some_and(|target| *target != daemon_target) {
+        return Ok(None);
+    }
+    writeln!(writer, "{}", message.to_text()?)?;
+    Ok(Some(WsMessage::text(read_line(&mut reader)?)))
+}
+
+fn read_line(reader: &mut BufReader<UnixStream>) -> anyhow::Result<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => anyhow::bail!("The daemon closed the connection without responding"),
+        Ok(_) => Ok(line.trim_end().to_owned()),
+        Err(error)
+            if matches!(
+                error.kind(),
+                IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+            ) =>
+        {
+            anyhow::bail!("Timed out waiting for the daemon to respond")
+        }
+        Err(error) => Err(error.into()),
+    }
+}
@@ -0,0 +1,73 @@
+//! Request data from files, stdin and `--set`/`--set-json` arguments.
+//!
+//! Fields are set by dot-separated paths, like `inputSettings.text`.
+//! Missing objects on the way are created, and numbers index arrays, where
+//! an index one past the end appends: `--set 0.requestType=GetVersion` on an
+//! empty batch adds its first request.
+
+use anyhow::Context as _;
+use serde_json::{Map, Value};
+use std::{io::Read as _, path::Path};
+
+/// `--set PATH=VALUE`, where the value is a string.
+pub fn parse_set(arg: &str) -> Result<(String, Value), String> {
+    let (path, value) = split(arg, "PATH=VALUE")?;
+    Ok((path, Value::String(value.to_owned())))
+}
+
+/// `--set-json PATH=JSON`.
+pub fn parse_set_json(arg: &str) -> Result<(String, Value), String> {
+    let (path, value) = split(arg, "PATH=JSON")?;
+    let value = serde_json::from_str(value).map_err(|error| format!("{error}"))?;
+    Ok((path, value))
+}
+
+fn split<'a>(arg: &'a str, usage: &str) -> Result<(String, &'a str), String> {
+    match arg.split_once('=') {
+        Some((path, value)) if !path.is_empty() => Ok((path.to_owned(), value)),
+        _ => Err(format!("expected {usage}, got {arg}")),
+    }
+}
+
+/// Reads JSON from `path`, or from stdin if it's `-`.
+pub fn read_file(path: &Path) -> anyhow::Result<Value> {
+    let text = if path == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read data from stdin")?;
+        text
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read data file {}", path.display()))?
+    };
+    serde_json::from_str(&text).with_context(|| format!("Invalid JSON in {}", path.display()))
+}
+
+/// Sets the field at `path` in `root` to `value`.
+pub fn set(root: &mut Value, path: &str, value: Value) -> anyhow::Result<()> {
+    let mut target = root;
+    for key in path.split('.') {
+        if target.is_null() {
+            *target = Value::Object(Map::new());
+        }
+        target = match target {
+            Value::Object(fields) => fields.entry(key).or_insert(Value::Null),
+            Value::Array(items) => {
+                let Some(index) = key.parse::<usize>().ok().filter(|i| *i <= items.len()) else {
+                    anyhow::bail!(
+                        "Can't set {path}: {key} is not an index into an array of {}",
+                        items.len()
+                    );
+                };
+                if index == items.len() {
+                    items.push(Value::Null);
+                }
+                &mut items[index]
+            }
+            value => anyhow::bail!("Can't set {path}: {key} is inside {value}"),
+        };
+    }
+    *target = value;
+    Ok(())
+}
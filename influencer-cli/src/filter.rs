@@ -0,0 +1,178 @@
+//! Choosing events by type and by their data.
+//!
+//! Event types are globs (`*` and `?`). Data predicates are written like jq
+//! paths into `eventData`, optionally compared to a value:
+//!
+//! ```text
+//! .outputActive
+//! .sceneName == "BRB"
+//! .inputs[0].inputName != Mic
+//! .inputVolumeDb < -20
+//! ```
+//!
+//! A path on its own requires the value to be neither missing, `null` nor
+//! `false`. Values are JSON, or strings if they aren't valid JSON. Missing
+//! values compare like `null`; `<`, `<=`, `>` and `>=` compare numbers with
+//! numbers and strings with strings, and are false for anything else.
+
+use influencer::message as m;
+use serde_json::Value;
+use std::cmp::Ordering;
+use wildmatch::WildMatch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+pub struct Predicate {
+    /// JSON pointer into `eventData`.
+    pointer: String,
+    /// No comparison means a truthiness check.
+    comparison: Option<(Op, Value)>,
+}
+impl Predicate {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let text = text.trim();
+        let end = text
+            .find(|c: char| c.is_whitespace() || "=!<>".contains(c))
+            .unwrap_or(text.len());
+        let (path, rest) = text.split_at(end);
+        let pointer = path_to_pointer(path)?;
+        let rest = rest.trim_start();
+        if rest.is_empty() {
+            return Ok(Self {
+                pointer,
+                comparison: None,
+            });
+        }
+        let ops = [
+            ("==", Op::Eq),
+            ("!=", Op::Ne),
+            ("<=", Op::Le),
+            (">=", Op::Ge),
+            ("<", Op::Lt),
+            (">", Op::Gt),
+        ];
+        let Some((value, op)) = ops
+            .iter()
+            .find_map(|(token, op)| Some((rest.strip_prefix(token)?, *op)))
+        else {
+            return Err(format!("expected a comparison after {path}, got {rest}"));
+        };
+        let value = value.trim();
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+        Ok(Self {
+            pointer,
+            comparison: Some((op, value)),
+        })
+    }
+    pub fn matches(&self, data: Option<&Value>) -> bool {
+        let value = data
+            .and_then(|data| data.pointer(&self.pointer))
+            .unwrap_or(&Value::Null);
+        let Some((op, ref expected)) = self.comparison else {
+            return !matches!(value, Value::Null | Value::Bool(false));
+        };
+        match op {
+            Op::Eq => equal(value, expected),
+            Op::Ne => !equal(value, expected),
+            Op::Lt => compare(value, expected) == Some(Ordering::Less),
+            Op::Le => matches!(
+                compare(value, expected),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Op::Gt => compare(value, expected) == Some(Ordering::Greater),
+            Op::Ge => matches!(
+                compare(value, expected),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+        }
+    }
+}
+
+/// Turns `.a.b[0]` into `/a/b/0`.
+pub fn path_to_pointer(path: &str) -> Result<String, String> {
+    if !path.starts_with('.') {
+        return Err(format!("expected a path starting with '.', got {path}"));
+    }
+    let mut pointer = String::new();
+    let mut rest = path;
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('[') {
+            let Some((index, after)) = after.split_once(']') else {
+                return Err(format!("unclosed [ in {path}"));
+            };
+            if index.parse::<usize>().is_err() {
+                return Err(format!("expected an array index, got [{index}] in {path}"));
+            }
+            pointer.push('/');
+            pointer.push_str(index);
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('.') {
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            let (key, after) = after.split_at(end);
+            // A lone "." is the whole of eventData
+            if !key.is_empty() {
+                pointer.push('/');
+                pointer.push_str(&key.replace('~', "~0").replace('/', "~1"));
+            } else if !after.is_empty() {
+                return Err(format!("empty key in {path}"));
+            }
+            rest = after;
+        } else {
+            return Err(format!("unexpected {rest} in {path}"));
+        }
+    }
+    Ok(pointer)
+}
+
+/// Like `==`, except that numbers are equal if their values are.
+fn equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64() == b.as_f64(),
+        (a, b) => a == b,
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Which events to act on. An empty filter matches everything.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    /// If not empty, event types must match one of these.
+    pub types: Vec<WildMatch>,
+    /// Event types must not match any of these.
+    pub exclude_types: Vec<WildMatch>,
+    /// Must all match.
+    pub predicates: Vec<Predicate>,
+}
+impl EventFilter {
+    pub fn matches(&self, event: &m::AnyEvent) -> bool {
+        (self.types.is_empty()
+            || self
+                .types
+                .iter()
+                .any(|pattern| pattern.matches(event.event_type)))
+            && !self
+                .exclude_types
+                .iter()
+                .any(|pattern| pattern.matches(event.event_type))
+            && self
+                .predicates
+                .iter()
+                .all(|predicate| predicate.matches(event.event_data.as_ref()))
+    }
+}
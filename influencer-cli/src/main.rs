@@ -1,14 +1,47 @@
-use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
+mod bindings;
+mod config;
+#[cfg(unix)]
+mod daemon;
+mod data;
+mod filter;
+mod on;
+mod output;
+mod pipe;
+mod policy;
+mod protocol;
+mod proxy;
+mod rules;
+mod script;
+mod session;
+mod shell;
+mod steps;
+mod wait;
+
+use anyhow::Context as _;
+use clap::{
+    Arg, ArgAction, ArgMatches, Command, builder::RangedU64ValueParser, parser::ValueSource,
+    value_parser,
+};
 use influencer::{
-    auth,
+    client::{self, Client},
     message::{self as m, IntoWsMessageJson as _, WsMessageExt as _},
 };
 use serde::{Deserialize, Serialize};
 use std::{
     io::{Write, stdout},
-    net::TcpStream,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tungstenite::{
+    Message as WsMessage,
+    http::Uri,
+    protocol::{CloseFrame, frame::coding::CloseCode},
 };
-use tungstenite::WebSocket;
+use wildmatch::WildMatch;
 
 fn main() -> Result<(), anyhow::Error> {
     fn parse_req_data(s: &str) -> serde_json::Result<serde_json::Value> {
@@ -23,11 +56,37 @@ fn main() -> Result<(), anyhow::Error> {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub request_data: Option<T>,
     }
-    fn parse_batch_data(s: &str) -> serde_json::Result<Vec<RequestsItem<serde_json::Value>>> {
-        serde_json::from_str(s)
+    fn parse_batch_data(
+        value: serde_json::Value,
+    ) -> serde_json::Result<Vec<RequestsItem<serde_json::Value>>> {
+        serde_json::from_value(value)
     }
     let command = clap::command!()
         .styles(style::CLAP_STYLING)
+        .after_help(
+            "Connection settings are taken from, in order of precedence: command-line options, \
+            OBS_WS_* environment variables, the selected profile of the config file, and \
+            built-in defaults.\n\n\
+            When OBS fails a request, request, batch and run exit with a code made from its \
+            requestStatus.code: ten times the first digit plus the last digit, like 51 for 501 \
+            (output not running). Other errors exit with 1.",
+        )
+        .arg(
+            Arg::new("config")
+                .value_name("PATH")
+                .long("config")
+                .env("INFLUENCER_CONFIG")
+                .value_parser(value_parser!(PathBuf))
+                .help("Config file [default: ~/.config/influencer/config.toml]"),
+        )
+        .arg(
+            Arg::new("profile")
+                .value_name("NAME")
+                .long("profile")
+                .short('P')
+                .env("INFLUENCER_PROFILE")
+                .help("Connection profile from the config file"),
+        )
         .arg(
             Arg::new("host")
                 .value_name("HOST")
@@ -35,7 +94,7 @@ fn main() -> Result<(), anyhow::Error> {
                 .short('H')
                 .env("OBS_WS_HOST")
                 .default_value("localhost")
-                .help("OBS websocket host"),
+                .help("OBS websocket host, or a ws:// or wss:// URL (port 443 by default for wss)"),
         )
         .arg(
             Arg::new("port")
@@ -56,6 +115,57 @@ fn main() -> Result<(), anyhow::Error> {
                 .hide_env_values(true)
                 .help("OBS websocket password"),
         )
+        .arg(
+            Arg::new("tls")
+                .long("tls")
+                .action(ArgAction::SetTrue)
+                .overrides_with("no-tls")
+                .help("Connect using TLS (wss://)"),
+        )
+        .arg(
+            Arg::new("no-tls")
+                .long("no-tls")
+                .action(ArgAction::SetTrue)
+                .overrides_with("tls")
+                .help("Connect without TLS, even if the profile says otherwise"),
+        )
+        .arg(
+            Arg::new("ca-file")
+                .value_name("PATH")
+                .long("ca-file")
+                .env("OBS_WS_CA_FILE")
+                .value_parser(value_parser!(PathBuf))
+                .help("PEM file of root certificates to trust instead of the default ones"),
+        )
+        .arg(
+            Arg::new("insecure")
+                .long("insecure")
+                .action(ArgAction::SetTrue)
+                .help("Skip TLS certificate verification (dangerous)"),
+        )
+        .arg(
+            Arg::new("keepalive")
+                .value_name("SECONDS")
+                .long("keepalive")
+                .default_value("30")
+                .value_parser(value_parser!(u64))
+                .help("Interval between keepalive pings (0 to disable)"),
+        )
+        .arg(
+            Arg::new("keepalive-timeout")
+                .value_name("SECONDS")
+                .long("keepalive-timeout")
+                .default_value("10")
+                .value_parser(value_parser!(u64).range(1..))
+                .help("Consider the connection dead if a ping goes unanswered this long"),
+        )
+        .arg(
+            Arg::new("record")
+                .value_name("FILE")
+                .long("record")
+                .value_parser(value_parser!(PathBuf))
+                .help("Append all traffic with OBS to FILE, as JSON lines"),
+        )
         .arg(
             Arg::new("compact")
                 .long("compact")
@@ -63,6 +173,28 @@ fn main() -> Result<(), anyhow::Error> {
                 .action(ArgAction::SetTrue)
                 .help("Compact JSON output"),
         )
+        .arg(
+            Arg::new("no-fail")
+                .long("no-fail")
+                .action(ArgAction::SetTrue)
+                .help("Exit with 0 even if OBS fails a request"),
+        )
+        .arg(
+            Arg::new("output")
+                .long("output")
+                .short('o')
+                .value_name("FORMAT")
+                .default_value("json")
+                .value_parser(value_parser!(output::Format))
+                .help("Output format"),
+        )
+        .arg(
+            Arg::new("field")
+                .long("field")
+                .value_name("PATH")
+                .value_parser(output::parse_field)
+                .help("Print only this part of each response or event, like responseData.outputActive or /responseData/outputActive"),
+        )
         .subcommand_required(true)
         .subcommand(
             Command::new("request")
@@ -78,7 +210,8 @@ fn main() -> Result<(), anyhow::Error> {
                         .value_name("DATA")
                         .help("JSON data for the request.")
                         .value_parser(parse_req_data),
-                ),
+                )
+                .args(data_args()),
         )
         .subcommand(
             Command::new("batch")
@@ -98,65 +231,636 @@ fn main() -> Result<(), anyhow::Error> {
                         .help("Stop processing requests after the first failure"),
                 )
                 .arg(
-                    Arg::new("requests")
+                    Arg::new("data")
                         .value_name("DATA")
                         .help("JSON array of requests")
+                        .required_unless_present_any(["data-file", "set", "set-json"])
+                        .value_parser(parse_req_data),
+                )
+                .args(data_args()),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Run a macro from the config file, or list them")
+                .arg(
+                    Arg::new("macro")
+                        .value_name("NAME")
+                        .help("Macro name. Lists all macros if not given"),
+                )
+                .arg(
+                    Arg::new("params")
+                        .value_name("PARAM=VALUE")
+                        .num_args(0..)
+                        .value_parser(config::parse_param)
+                        .help(
+                            "Macro parameters, filling {PARAM} as a string, or {PARAM:json} \
+                            parsed as JSON",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("run-file")
+                .about("Run a file of requests, sleeps and waits, in order")
+                .after_help(
+                    "The steps file is TOML, with a [[step]] table per step, or YAML \
+                    (.yaml, .yml) with a list of the same entries under step:\n\n\
+                    [[step]]\n\
+                    request = \"GetSceneItemId\"\n\
+                    data = { sceneName = \"{scene}\", sourceName = \"Webcam\" }\n\
+                    capture = { id = \".sceneItemId\" }\n\n\
+                    [[step]]\n\
+                    wait = \"SceneItemEnableStateChanged\"\n\
+                    where = [\".sceneItemId == {id}\"]\n\
+                    timeout = 5\n\n\
+                    [[step]]\n\
+                    sleep = 0.5\n\n\
+                    Requests may also have assert = [\"PREDICATE\", ...]. {NAME} \
+                    placeholders are filled from captures and NAME=VALUE arguments.",
+                )
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
                         .required(true)
-                        .value_parser(parse_batch_data),
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Steps file (TOML or YAML)"),
+                )
+                .arg(
+                    Arg::new("params")
+                        .value_name("NAME=VALUE")
+                        .num_args(0..)
+                        .value_parser(config::parse_param)
+                        .help("Variables for the steps' placeholders"),
                 ),
         )
         .subcommand(
-            Command::new("events").about("Listen for events").arg(
-                Arg::new("event-subs")
-                    .value_name("BITMASK")
-                    .help("Event types bitmask")
-                    .value_parser(value_parser!(u32)),
-            ),
+            Command::new("bindings")
+                .about("Generate global shortcuts from the bindings in the config file")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("export")
+                        .about("Print shortcut configuration for a compositor or desktop")
+                        .arg(
+                            Arg::new("format")
+                                .long("format")
+                                .short('f')
+                                .required(true)
+                                .value_parser(value_parser!(bindings::Format)),
+                        )
+                        .arg(
+                            Arg::new("program")
+                                .long("program")
+                                .value_name("PATH")
+                                .default_value("influencer")
+                                .help("The influencer executable the shortcuts run"),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("events")
+                .about("Listen for events")
+                .arg(
+                    Arg::new("reconnect")
+                        .long("reconnect")
+                        .action(ArgAction::SetTrue)
+                        .help("Reconnect instead of exiting when the connection is lost"),
+                )
+                .arg(
+                    Arg::new("event-subs")
+                        .value_name("BITMASK")
+                        .help("Event types bitmask")
+                        .value_parser(value_parser!(u32)),
+                )
+                .args(filter_args())
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .short('n')
+                        .value_name("N")
+                        .value_parser(value_parser!(u64).range(1..))
+                        .help("Exit after printing N events"),
+                )
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .value_parser(parse_seconds)
+                        .help("Exit after listening this long"),
+                )
+                .arg(
+                    Arg::new("with-timestamp")
+                        .long("with-timestamp")
+                        .action(ArgAction::SetTrue)
+                        .help("Add the local time each event was received at, as receivedAt"),
+                ),
+        )
+        .subcommand(
+            Command::new("on")
+                .about("Run a command for each matching event")
+                .after_help(
+                    "The command gets the event as JSON on stdin, and its type, intent and \
+                    data in OBS_EVENT_TYPE, OBS_EVENT_INTENT and OBS_EVENT_DATA. Each field of \
+                    the data is also in OBS_EVENT_DATA_<FIELD>, like OBS_EVENT_DATA_SCENE_NAME.",
+                )
+                .arg(
+                    Arg::new("event-type")
+                        .value_name("EVENT_TYPE")
+                        .required(true)
+                        .help("Event type to act on (glob pattern)"),
+                )
+                .arg(where_arg())
+                .arg(
+                    Arg::new("concurrency")
+                        .long("concurrency")
+                        .short('j')
+                        .value_name("N")
+                        .default_value("1")
+                        .value_parser(RangedU64ValueParser::<usize>::new().range(1..))
+                        .help("How many commands may run at once; other events wait"),
+                )
+                .arg(
+                    Arg::new("debounce")
+                        .long("debounce")
+                        .value_name("SECONDS")
+                        .value_parser(parse_seconds)
+                        .help("Only run for the last event once none has matched for this long"),
+                )
+                .arg(
+                    Arg::new("reconnect")
+                        .long("reconnect")
+                        .action(ArgAction::SetTrue)
+                        .help("Reconnect instead of exiting when the connection is lost"),
+                )
+                .arg(
+                    Arg::new("command")
+                        .value_name("COMMAND")
+                        .required(true)
+                        .num_args(1..)
+                        .last(true)
+                        .help("Program and arguments to run, after --"),
+                ),
+        )
+        .subcommand(
+            Command::new("rules")
+                .about("Send requests when events happen, as described by a rules file")
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help("TOML file of [[rule]] entries, or a YAML file of rule: entries"),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .short('n')
+                        .action(ArgAction::SetTrue)
+                        .help("Print the requests rules would send instead of sending them"),
+                ),
+        )
+        .subcommand(
+            Command::new("script")
+                .about("Run a Rhai script with access to OBS")
+                .after_help(
+                    "Scripts can use request(type, data), batch(requests), on(event_type, fn), \
+                    after(ms, fn), every(ms, fn), cancel(id) and sleep(ms). Arguments are in ARGS.",
+                )
+                .arg(
+                    Arg::new("file")
+                        .value_name("FILE")
+                        .required(true)
+                        .value_parser(value_parser!(PathBuf))
+                        .help("Script file"),
+                )
+                .arg(
+                    Arg::new("args")
+                        .value_name("ARGS")
+                        .num_args(0..)
+                        .trailing_var_arg(true)
+                        .allow_hyphen_values(true)
+                        .help("Arguments for the script"),
+                ),
+        )
+        .subcommand(
+            Command::new("wait-for")
+                .about("Wait for an event, optionally after sending a request")
+                .after_help(
+                    "Prints the event and exits with 0, or exits with 124 on timeout. \
+                    Subscribes to the events needed for EVENT_TYPE; high-volume ones like \
+                    InputVolumeMeters only when named in full.",
+                )
+                .arg(
+                    Arg::new("event-type")
+                        .value_name("EVENT_TYPE")
+                        .required(true)
+                        .help("Event type to wait for (glob pattern)"),
+                )
+                .arg(where_arg())
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .value_parser(parse_seconds)
+                        .help("Give up after waiting this long"),
+                )
+                .arg(
+                    Arg::new("request")
+                        .long("request")
+                        .short('r')
+                        .value_name("TYPE")
+                        .help("Request to send once subscribed, failing if it fails"),
+                )
+                .arg(
+                    Arg::new("request-data")
+                        .long("request-data")
+                        .short('d')
+                        .value_name("DATA")
+                        .requires("request")
+                        .value_parser(parse_req_data)
+                        .help("JSON data for --request"),
+                ),
+        )
+        .subcommand(
+            Command::new("shell")
+                .about("Send requests interactively over one connection")
+                .arg(events_arg()),
+        )
+        .subcommand(
+            Command::new("pipe")
+                .about("Read requests from stdin and write responses to stdout, as NDJSON")
+                .arg(events_arg())
+                .arg(
+                    Arg::new("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .value_parser(parse_seconds)
+                        .default_value("10")
+                        .help("How long to wait for outstanding responses once stdin is closed"),
+                ),
+        )
+        .subcommand(
+            Command::new("proxy")
+                .about("Share one OBS connection among many clients")
+                .arg(
+                    Arg::new("listen")
+                        .value_name("ADDRESS")
+                        .long("listen")
+                        .short('l')
+                        .default_value("127.0.0.1:4456")
+                        .help("Address to accept client connections on"),
+                )
+                .arg(
+                    Arg::new("client-password")
+                        .value_name("PASSWORD")
+                        .long("client-password")
+                        .env("INFLUENCER_PROXY_PASSWORD")
+                        .hide_env_values(true)
+                        .help("Password clients must authenticate with (none if unset)"),
+                )
+                .arg(
+                    Arg::new("policy")
+                        .value_name("FILE")
+                        .long("policy")
+                        .value_parser(value_parser!(PathBuf))
+                        .help("TOML file of rules for which requests clients may send"),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("List the request or event types of the protocol, without connecting")
+                .arg(
+                    Arg::new("kind")
+                        .value_name("KIND")
+                        .required(true)
+                        .value_parser(["requests", "events"]),
+                )
+                .arg(
+                    Arg::new("category")
+                        .long("category")
+                        .value_name("CATEGORY")
+                        .help("Only types in this category, like inputs or \"scene items\""),
+                ),
+        )
+        .subcommand(
+            Command::new("describe")
+                .about("Describe a request or event type and its fields, without connecting")
+                .arg(
+                    Arg::new("type")
+                        .value_name("TYPE")
+                        .required(true)
+                        .help("Request or event type, like SetInputVolume"),
+                ),
+        );
+    #[cfg(unix)]
+    let command = command
+        .arg(
+            Arg::new("socket")
+                .value_name("PATH")
+                .long("socket")
+                .env("INFLUENCER_SOCKET")
+                .value_parser(value_parser!(PathBuf))
+                .help("Unix socket of the daemon [default: $XDG_RUNTIME_DIR/influencer.sock]"),
+        )
+        .arg(
+            Arg::new("no-daemon")
+                .long("no-daemon")
+                .action(ArgAction::SetTrue)
+                .help("Connect directly even if a daemon is running"),
+        )
+        .subcommand(
+            Command::new("daemon")
+                .about("Keep a connection open for request and batch, on a Unix socket"),
         );
     let matches = command.get_matches();
     let pretty = !matches.get_flag("compact");
+    let output = output::Output::new(
+        *matches.get_one::<output::Format>("output").unwrap(),
+        pretty,
+        matches.get_one::<String>("field").cloned(),
+    );
     match matches.subcommand() {
         Some(("request", sub_matches)) => {
-            let request_id = ":3";
-            let request = m::Request {
-                request_type: sub_matches.get_one::<String>("req_type").unwrap(),
-                request_id,
-                request_data: sub_matches.get_one::<serde_json::Value>("data"),
-            };
-            let mut ws = connect(&matches, Some(0))?;
-            ws.send(request.into_ws_message_json()?)?;
-            let response = ws.read()?;
-            let response = response.obs_message_data::<m::AnyResponse>()?;
-            assert_eq!(response.request_id, request_id);
-            json_print(pretty, &response)?;
+            let request_type = sub_matches.get_one::<String>("req_type").unwrap();
+            let data = request_data(sub_matches, serde_json::json!({}))?;
+            exit(print_request(
+                &matches,
+                &output,
+                request_type,
+                data.as_ref(),
+            )?);
         }
         Some(("batch", sub_matches)) => {
-            let requests_list = sub_matches
-                .get_one::<Vec<RequestsItem<serde_json::Value>>>("requests")
-                .unwrap();
+            let requests = request_data(sub_matches, serde_json::json!([]))?.unwrap();
+            let requests_list =
+                parse_batch_data(requests).context("Invalid requests for the batch")?;
             let execution_type = sub_matches.get_one::<i32>("execution-type").copied();
             let halt_on_failure = sub_matches.get_flag("halt-on-failure");
-            let request_id = ":3";
-            let request = m::RequestBatch {
-                request_id,
-                halt_on_failure: Some(halt_on_failure),
+            exit(print_batch(
+                &matches,
+                &output,
+                &requests_list,
+                Some(halt_on_failure),
                 execution_type,
-                requests: requests_list,
+            )?);
+        }
+        Some(("bindings", sub_matches)) => match sub_matches.subcommand() {
+            Some(("export", sub_matches)) => {
+                let format = *sub_matches.get_one::<bindings::Format>("format").unwrap();
+                let program = sub_matches.get_one::<String>("program").unwrap();
+                print!(
+                    "{}",
+                    bindings::export(&config_file(&matches)?, format, program)?
+                );
+            }
+            _ => unreachable!(),
+        },
+        Some(("run", sub_matches)) => {
+            let file = config_file(&matches)?;
+            let Some(name) = sub_matches.get_one::<String>("macro") else {
+                for (name, macro_) in &file.macros {
+                    let mut usage = name.clone();
+                    for param in macro_.param_names() {
+                        usage = format!("{usage} {param}");
+                    }
+                    match macro_.description {
+                        Some(ref description) => println!("{usage}\t{description}"),
+                        None => println!("{usage}"),
+                    }
+                }
+                return Ok(());
+            };
+            let params: Vec<(String, String)> = sub_matches
+                .get_many::<(String, String)>("params")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            match file.macro_(name)?.expand(&params)? {
+                config::Expanded::Request { request_type, data } => {
+                    exit(print_request(
+                        &matches,
+                        &output,
+                        &request_type,
+                        data.as_ref(),
+                    )?);
+                }
+                config::Expanded::Batch {
+                    requests,
+                    halt_on_failure,
+                    execution_type,
+                } => {
+                    exit(print_batch(
+                        &matches,
+                        &output,
+                        &requests,
+                        halt_on_failure,
+                        execution_type,
+                    )?);
+                }
+            }
+        }
+        Some(("run-file", sub_matches)) => {
+            let steps = steps::Steps::load(sub_matches.get_one::<PathBuf>("file").unwrap())?;
+            let params: Vec<(String, String)> = sub_matches
+                .get_many::<(String, String)>("params")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let event_subscriptions = match steps.event_types()[..] {
+                [] => m::event_subscription::NONE,
+                ref event_types => {
+                    protocol::event_subscriptions(event_types).unwrap_or(m::event_subscription::ALL)
+                }
             };
-            let mut ws = connect(&matches, Some(0))?;
-            ws.send(request.into_ws_message_json()?)?;
-            let response = ws.read()?;
-            let response = response.obs_message_data::<m::AnyResponseBatch>()?;
-            assert_eq!(response.request_id, request_id);
-            json_print(pretty, &response)?;
+            let mut client = connect(&matches, Some(event_subscriptions), false)?;
+            let result = steps::run(&mut client, &steps, &params, &output);
+            close(&mut client);
+            result?;
         }
         Some(("events", sub_matches)) => {
-            let event_subscriptions = sub_matches.get_one::<u32>("event-subs").copied();
-            let mut ws = connect(&matches, event_subscriptions)?;
-            loop {
-                let event = ws.read()?;
+            let filter = event_filter(sub_matches);
+            let event_subscriptions = match sub_matches.get_one::<u32>("event-subs") {
+                Some(bitmask) => Some(*bitmask),
+                None => protocol::event_subscriptions(&filter.types),
+            };
+            let reconnect = sub_matches.get_flag("reconnect");
+            let mut count = sub_matches.get_one::<u64>("count").copied();
+            let deadline = sub_matches
+                .get_one::<Duration>("timeout")
+                .map(|timeout| Instant::now() + *timeout);
+            let with_timestamp = sub_matches.get_flag("with-timestamp");
+            let mut client = connect(&matches, event_subscriptions, reconnect)?;
+            let interrupted = interrupt_flag()?;
+            while !interrupted.load(Ordering::Relaxed)
+                && count != Some(0)
+                && deadline.is_none_or(|deadline| Instant::now() < deadline)
+            {
+                let Some(event) = client.try_read()? else {
+                    continue;
+                };
                 let event = event.obs_message_data::<m::AnyEvent>()?;
-                json_print(pretty, &event)?;
+                if !filter.matches(&event) {
+                    continue;
+                }
+                match with_timestamp {
+                    true => output.print(&Timestamped::now(event))?,
+                    false => output.print(&event)?,
+                }
+                count = count.map(|count| count - 1);
+            }
+            close(&mut client);
+        }
+        Some(("on", sub_matches)) => {
+            let event_type = sub_matches.get_one::<String>("event-type").unwrap();
+            let filter = filter::EventFilter {
+                types: vec![WildMatch::new(event_type)],
+                predicates: predicates(sub_matches),
+                ..Default::default()
+            };
+            let event_subscriptions =
+                protocol::event_subscriptions(&filter.types).unwrap_or(m::event_subscription::ALL);
+            let options = on::Options {
+                command: sub_matches
+                    .get_many::<String>("command")
+                    .unwrap()
+                    .cloned()
+                    .collect(),
+                concurrency: *sub_matches.get_one::<usize>("concurrency").unwrap(),
+                debounce: sub_matches.get_one::<Duration>("debounce").copied(),
+            };
+            let reconnect = sub_matches.get_flag("reconnect");
+            let mut client = connect(&matches, Some(event_subscriptions), reconnect)?;
+            let interrupted = interrupt_flag()?;
+            on::run(&mut client, &filter, &options, &interrupted)?;
+            close(&mut client);
+        }
+        Some(("rules", sub_matches)) => {
+            let rules = rules::Rules::load(sub_matches.get_one::<PathBuf>("file").unwrap())?;
+            let event_subscriptions = protocol::event_subscriptions(&rules.event_types())
+                .unwrap_or(m::event_subscription::ALL);
+            let mut client = connect(&matches, Some(event_subscriptions), true)?;
+            let interrupted = interrupt_flag()?;
+            let dry_run = sub_matches.get_flag("dry-run");
+            rules::run(&mut client, &rules, dry_run, &output, &interrupted)?;
+            close(&mut client);
+        }
+        Some(("script", sub_matches)) => {
+            let path = sub_matches.get_one::<PathBuf>("file").unwrap();
+            let args = sub_matches
+                .get_many::<String>("args")
+                .unwrap_or_default()
+                .cloned()
+                .collect();
+            let interrupted = interrupt_flag()?;
+            match script::run(client_config(&matches)?, path, args, &interrupted)? {
+                Some(0) | None => {}
+                Some(code) => std::process::exit(code),
+            }
+        }
+        Some(("wait-for", sub_matches)) => {
+            let event_type = sub_matches.get_one::<String>("event-type").unwrap();
+            let filter = filter::EventFilter {
+                types: vec![WildMatch::new(event_type)],
+                predicates: predicates(sub_matches),
+                ..Default::default()
+            };
+            // Unknown event types may still exist, so subscribe to all but the
+            // high-volume ones
+            let event_subscriptions =
+                protocol::event_subscriptions(&filter.types).unwrap_or(m::event_subscription::ALL);
+            let request = match sub_matches.get_one::<String>("request") {
+                Some(request_type) => Some(
+                    m::Request {
+                        request_type,
+                        request_id: "wait-for",
+                        request_data: sub_matches.get_one::<serde_json::Value>("request-data"),
+                    }
+                    .into_ws_message_json()?,
+                ),
+                None => None,
+            };
+            let timeout = sub_matches.get_one::<Duration>("timeout").copied();
+            let mut client = connect(&matches, Some(event_subscriptions), false)?;
+            let event = wait::run(&mut client, &filter, request, timeout)?;
+            close(&mut client);
+            match event {
+                Some(event) => output.print(&event.obs_message_data::<m::AnyEvent>()?)?,
+                None => {
+                    eprintln!("Timed out waiting for {event_type}");
+                    std::process::exit(wait::EXIT_TIMEOUT);
+                }
+            }
+        }
+        Some(("shell", sub_matches)) => {
+            reject_output_options(&matches, "shell")?;
+            let event_subscriptions = sub_matches.get_one::<u32>("events").copied();
+            let mut config = client_config(&matches)?;
+            config.event_subscriptions = Some(event_subscriptions.unwrap_or(0));
+            config.auto_reconnect = true;
+            shell::run(config, event_subscriptions.is_some(), pretty)?;
+        }
+        Some(("pipe", sub_matches)) => {
+            reject_output_options(&matches, "pipe")?;
+            let event_subscriptions = sub_matches.get_one::<u32>("events").copied();
+            let mut config = client_config(&matches)?;
+            config.event_subscriptions = Some(event_subscriptions.unwrap_or(0));
+            let timeout = *sub_matches.get_one::<Duration>("timeout").unwrap();
+            pipe::run(config, timeout)?;
+        }
+        #[cfg(unix)]
+        Some(("daemon", _)) => {
+            daemon::run(client_config(&matches)?, &socket_path(&matches))?;
+        }
+        Some(("proxy", sub_matches)) => {
+            let mut config = client_config(&matches)?;
+            config.auto_reconnect = true;
+            let listen = sub_matches.get_one::<String>("listen").unwrap();
+            let password = sub_matches.get_one::<String>("client-password").cloned();
+            let policy = match sub_matches.get_one::<PathBuf>("policy") {
+                Some(path) => policy::Policy::load(path)?,
+                None => policy::Policy::default(),
+            };
+            proxy::run(config, listen, password, policy)?;
+        }
+        Some(("list", sub_matches)) => {
+            let category = sub_matches.get_one::<String>("category");
+            let in_category =
+                |c: &String| category.is_none_or(|category| c.eq_ignore_ascii_case(category));
+            let protocol = &protocol::PROTOCOL;
+            let explicit_output = explicit::<output::Format>(&matches, "output").is_some();
+            match sub_matches.get_one::<String>("kind").unwrap().as_str() {
+                "requests" => {
+                    for request in protocol
+                        .requests
+                        .iter()
+                        .filter(|r| in_category(&r.category))
+                    {
+                        if explicit_output {
+                            output.print(request)?;
+                        } else {
+                            let summary = protocol::summary(&request.description);
+                            println!("{}\t{summary}", request.request_type);
+                        }
+                    }
+                }
+                _ => {
+                    for event in protocol.events.iter().filter(|e| in_category(&e.category)) {
+                        if explicit_output {
+                            output.print(event)?;
+                        } else {
+                            let summary = protocol::summary(&event.description);
+                            println!("{}\t{summary}", event.event_type);
+                        }
+                    }
+                }
+            }
+        }
+        Some(("describe", sub_matches)) => {
+            let name = sub_matches.get_one::<String>("type").unwrap();
+            let entry = protocol::find(name)
+                .with_context(|| format!("Unknown request or event type {name}"))?;
+            if explicit::<output::Format>(&matches, "output").is_some() {
+                output.print(&entry)?;
+            } else {
+                print!("{}", protocol::describe(&entry));
             }
         }
         _ => unreachable!(),
@@ -164,6 +868,167 @@ fn main() -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+/// `--data-file`, `--set` and `--set-json`, for commands that take JSON data.
+fn data_args() -> [Arg; 3] {
+    [
+        Arg::new("data-file")
+            .long("data-file")
+            .value_name("PATH")
+            .conflicts_with("data")
+            .value_parser(value_parser!(PathBuf))
+            .help("Read the JSON data from a file, or from stdin if PATH is -"),
+        Arg::new("set")
+            .long("set")
+            .value_name("PATH=VALUE")
+            .action(ArgAction::Append)
+            .value_parser(data::parse_set)
+            .help("Set a field of the data to a string, like inputSettings.text=Hello"),
+        Arg::new("set-json")
+            .long("set-json")
+            .value_name("PATH=JSON")
+            .action(ArgAction::Append)
+            .value_parser(data::parse_set_json)
+            .help("Set a field of the data to a JSON value, like inputMuted=true"),
+    ]
+}
+
+/// The data given by `data_args` and the `DATA` argument, if any. Fields
+/// are set in `empty` if there is nothing else to set them in.
+fn request_data(
+    matches: &ArgMatches,
+    empty: serde_json::Value,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    let mut data = match matches.get_one::<PathBuf>("data-file") {
+        Some(path) => Some(data::read_file(path)?),
+        None => matches.get_one::<serde_json::Value>("data").cloned(),
+    };
+    // In the order they were given, so that later ones win
+    let mut fields = Vec::new();
+    for id in ["set", "set-json"] {
+        if let (Some(indices), Some(values)) = (
+            matches.indices_of(id),
+            matches.get_many::<(String, serde_json::Value)>(id),
+        ) {
+            fields.extend(indices.zip(values));
+        }
+    }
+    fields.sort_by_key(|(index, _)| *index);
+    for (_, (path, value)) in fields {
+        data::set(
+            data.get_or_insert_with(|| empty.clone()),
+            path,
+            value.clone(),
+        )?;
+    }
+    Ok(data)
+}
+
+/// `--events [BITMASK]`, for commands that only print events on request.
+fn events_arg() -> Arg {
+    Arg::new("events")
+        .long("events")
+        .value_name("BITMASK")
+        .num_args(0..=1)
+        // All non-high-volume events, like OBS's default
+        .default_missing_value("2047")
+        .value_parser(value_parser!(u32))
+        .help("Also print events, optionally only the given types")
+}
+
+/// Set by Ctrl-C, for commands that listen until interrupted.
+fn interrupt_flag() -> anyhow::Result<Arc<AtomicBool>> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let interrupted_ = interrupted.clone();
+    ctrlc::set_handler(move || {
+        // A second Ctrl-C exits immediately, e.g. while reconnecting
+        if interrupted_.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+    })?;
+    Ok(interrupted)
+}
+
+/// `--type`, `--exclude-type` and `--where`, for commands acting on events.
+fn filter_args() -> [Arg; 3] {
+    [
+        Arg::new("type")
+            .long("type")
+            .short('t')
+            .value_name("TYPE")
+            .action(ArgAction::Append)
+            .help(
+                "Only events of this type (glob pattern, repeatable). High-volume events \
+                like InputVolumeMeters are only subscribed to when named in full",
+            ),
+        Arg::new("exclude-type")
+            .long("exclude-type")
+            .short('x')
+            .value_name("TYPE")
+            .action(ArgAction::Append)
+            .help("No events of this type (glob pattern, repeatable)"),
+        where_arg(),
+    ]
+}
+
+fn where_arg() -> Arg {
+    Arg::new("where")
+        .long("where")
+        .short('w')
+        .value_name("EXPR")
+        .action(ArgAction::Append)
+        .value_parser(filter::Predicate::parse)
+        .help("Only events whose data matches, like '.sceneName == \"BRB\"' (repeatable)")
+}
+
+fn event_filter(matches: &ArgMatches) -> filter::EventFilter {
+    let patterns = |id| {
+        matches
+            .get_many::<String>(id)
+            .unwrap_or_default()
+            .map(|pattern| WildMatch::new(pattern))
+            .collect()
+    };
+    filter::EventFilter {
+        types: patterns("type"),
+        exclude_types: patterns("exclude-type"),
+        predicates: predicates(matches),
+    }
+}
+
+fn predicates(matches: &ArgMatches) -> Vec<filter::Predicate> {
+    matches
+        .get_many::<filter::Predicate>("where")
+        .unwrap_or_default()
+        .cloned()
+        .collect()
+}
+
+fn parse_seconds(arg: &str) -> Result<Duration, String> {
+    arg.parse::<f64>()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+        .ok_or_else(|| format!("expected a number of seconds, got {arg}"))
+}
+
+/// An event with the local time it was received at.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Timestamped<T> {
+    #[serde(flatten)]
+    event: T,
+    received_at: String,
+}
+impl<T> Timestamped<T> {
+    fn now(event: T) -> Self {
+        Self {
+            event,
+            received_at: jiff::Zoned::now()
+                .strftime("%Y-%m-%dT%H:%M:%S%.3f%:z")
+                .to_string(),
+        }
+    }
+}
+
 fn json_print<T: Serialize>(pretty: bool, data: &T) -> Result<(), serde_json::Error> {
     let mut out = stdout();
     json_serialize(pretty, data, &mut out)?;
@@ -183,18 +1048,277 @@ fn json_serialize<T: Serialize, W: Write>(
     }
 }
 
+/// Sends a request and prints the response. Returns the exit code.
+fn print_request(
+    matches: &ArgMatches,
+    output: &output::Output,
+    request_type: &str,
+    data: Option<&serde_json::Value>,
+) -> anyhow::Result<i32> {
+    let request_id = ":3";
+    let request = m::Request {
+        request_type,
+        request_id,
+        request_data: data,
+    };
+    let response = send_request(matches, request.into_ws_message_json()?)?;
+    let response = response.obs_message_data::<m::AnyResponse>()?;
+    check_request_id(response.request_id, request_id)?;
+    output.print(&response)?;
+    Ok(failure(
+        matches,
+        response.request_type,
+        &response.request_status,
+    ))
+}
+
+/// Sends a batch of requests and prints the response. Returns the exit
+/// code, which is that of the first failed request.
+fn print_batch<T: Serialize>(
+    matches: &ArgMatches,
+    output: &output::Output,
+    requests: T,
+    halt_on_failure: Option<bool>,
+    execution_type: Option<i32>,
+) -> anyhow::Result<i32> {
+    let request_id = ":3";
+    let request = m::RequestBatch {
+        request_id,
+        halt_on_failure,
+        execution_type,
+        requests,
+    };
+    let response = send_request(matches, request.into_ws_message_json()?)?;
+    let response = response.obs_message_data::<m::AnyResponseBatch>()?;
+    check_request_id(response.request_id, request_id)?;
+    output.print(&response)?;
+    let mut code = 0;
+    for result in &response.results {
+        let result_code = failure(matches, result.request_type, &result.request_status);
+        if code == 0 {
+            code = result_code;
+        }
+    }
+    Ok(code)
+}
+
+fn check_request_id(received: &str, sent: &str) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        received == sent,
+        "Got the response to request {received:?} instead of {sent:?}"
+    );
+    Ok(())
+}
+
+/// The exit code for a request, reporting it on stderr if it failed. Always
+/// 0 with `--no-fail`.
+fn failure(matches: &ArgMatches, request_type: &str, status: &m::response::RequestStatus) -> i32 {
+    if status.result || matches.get_flag("no-fail") {
+        return 0;
+    }
+    eprintln!(
+        "{request_type} failed with code {}: {}",
+        status.code,
+        status.comment.unwrap_or("no comment")
+    );
+    exit_code(status.code)
+}
+
+/// Ten times the first digit of a `RequestStatus` code plus its last digit,
+/// which fits all the codes obs-websocket has, or 1 for unexpected ones.
+fn exit_code(status_code: i32) -> i32 {
+    match status_code {
+        200..1000 if status_code % 100 < 10 => status_code / 100 * 10 + status_code % 10,
+        _ => 1,
+    }
+}
+
+/// Exits with `code` unless it's 0.
+fn exit(code: i32) {
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+
+/// Sends a request or batch message and returns the response. Goes through
+/// the daemon if one is running, and connects directly otherwise.
+fn send_request(matches: &ArgMatches, message: WsMessage) -> anyhow::Result<WsMessage> {
+    #[cfg(unix)]
+    if !matches.get_flag("no-daemon") {
+        // Connection settings that were asked for must match the daemon's
+        let explicit = CONNECTION_ARGS.iter().any(|id| {
+            matches!(matches.value_source(id), Some(source) if source != ValueSource::DefaultValue)
+        });
+        let target = match explicit {
+            true => Some(daemon::Target::of(&client_config(matches)?)),
+            false => None,
+        };
+        if let Some(response) = daemon::call(&socket_path(matches), &message, target.as_ref())? {
+            return Ok(response);
+        }
+    }
+    let mut client = connect(matches, Some(0), false)?;
+    client.send(message)?;
+    let response = client.read()?;
+    close(&mut client);
+    Ok(response)
+}
+
+/// The global arguments that decide where to connect to.
+#[cfg(unix)]
+const CONNECTION_ARGS: [&str; 9] = [
+    "host", "port", "password", "tls", "no-tls", "ca-file", "insecure", "profile", "config",
+];
+
+#[cfg(unix)]
+fn socket_path(matches: &ArgMatches) -> PathBuf {
+    matches
+        .get_one::<PathBuf>("socket")
+        .cloned()
+        .unwrap_or_else(daemon::default_socket_path)
+}
+
 fn connect(
     matches: &ArgMatches,
     event_subscriptions: Option<u32>,
-) -> anyhow::Result<WebSocket<TcpStream>> {
-    let host: &String = matches.get_one("host").unwrap();
-    let port: &u16 = matches.get_one("port").unwrap();
-    let password = matches.get_one::<String>("password").map(|v| v.as_str());
-    let stream = TcpStream::connect((host.as_str(), *port))?;
-    let (ws, _res) = tungstenite::client::client(format!("ws://{host}:{port}"), stream)?;
-    let auth = auth::AuthMachine::new(ws, password, event_subscriptions);
-    let (ws, _) = auth.drive().ready()?;
-    Ok(ws)
+    auto_reconnect: bool,
+) -> anyhow::Result<Client> {
+    let mut config = client_config(matches)?;
+    config.event_subscriptions = event_subscriptions;
+    config.auto_reconnect = auto_reconnect;
+    // Short enough for Ctrl-C to feel responsive while waiting for events
+    config.read_timeout = Some(Duration::from_millis(250));
+    Ok(Client::connect(config)?)
+}
+
+/// Connection settings from the global arguments.
+fn client_config(matches: &ArgMatches) -> anyhow::Result<client::Config> {
+    let file = config_file(matches)?;
+    let profile = file.profile(matches.get_one::<String>("profile").map(String::as_str))?;
+    let no_profile = config::Profile::default();
+    let profile = profile.unwrap_or(&no_profile);
+    let mut host = explicit::<String>(matches, "host")
+        .or(profile.host.as_ref())
+        .or(matches.get_one::<String>("host"))
+        .unwrap()
+        .clone();
+    let explicit_port = explicit::<u16>(matches, "port").or(profile.port.as_ref());
+    let mut port = *explicit_port.or(matches.get_one::<u16>("port")).unwrap();
+    let mut tls = match matches.get_flag("no-tls") {
+        true => false,
+        false => matches.get_flag("tls") || profile.tls.unwrap_or(false),
+    };
+    let mut path = "/".to_owned();
+    if host.contains("://") {
+        let uri: Uri = host.parse()?;
+        tls |= match uri.scheme_str() {
+            Some("ws") => false,
+            Some("wss") if matches.get_flag("no-tls") => {
+                anyhow::bail!("--no-tls contradicts the wss:// URL {host}")
+            }
+            Some("wss") => true,
+            _ => anyhow::bail!("Unsupported URL scheme in {host} (expected ws or wss)"),
+        };
+        port = match (uri.port_u16(), explicit_port) {
+            (Some(port), _) | (None, Some(&port)) => port,
+            // Behind a TLS-terminating proxy, most likely
+            (None, None) if tls => 443,
+            (None, None) => port,
+        };
+        if let Some(path_and_query) = uri.path_and_query() {
+            path = path_and_query.as_str().to_owned();
+        }
+        host = uri.host().unwrap_or_default().to_owned();
+    }
+    // Uri keeps the brackets of IPv6 addresses
+    if let Some(address) = host.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+        host = address.to_owned();
+    }
+    if !tls {
+        for option in ["ca-file", "insecure"] {
+            if matches.value_source(option) == Some(ValueSource::CommandLine) {
+                anyhow::bail!("--{option} needs TLS (--tls, or a wss:// URL)");
+            }
+        }
+    }
+    let keepalive = match *matches.get_one::<u64>("keepalive").unwrap() {
+        0 => None,
+        interval => Some(client::Keepalive {
+            interval: Duration::from_secs(interval),
+            timeout: Duration::from_secs(*matches.get_one::<u64>("keepalive-timeout").unwrap()),
+        }),
+    };
+    let mut config = client::Config::new(host, port);
+    config.path = path;
+    config.password = matches
+        .get_one::<String>("password")
+        .or(profile.password.as_ref())
+        .cloned();
+    #[cfg(feature = "tls")]
+    if tls {
+        config.tls = Some(client::TlsOptions {
+            ca_file: matches
+                .get_one::<PathBuf>("ca-file")
+                .or(profile.ca_file.as_ref())
+                .cloned(),
+            insecure_skip_verify: matches.get_flag("insecure") || profile.insecure.unwrap_or(false),
+        });
+    }
+    #[cfg(not(feature = "tls"))]
+    if tls {
+        anyhow::bail!("This build of influencer does not support TLS (see the `tls` feature)");
+    }
+    config.keepalive = keepalive;
+    config.record = matches.get_one::<PathBuf>("record").cloned();
+    Ok(config)
+}
+
+/// `shell` and `pipe` print responses their own way, so an output format
+/// or field asked for would be ignored.
+fn reject_output_options(matches: &ArgMatches, subcommand: &str) -> anyhow::Result<()> {
+    for option in ["output", "field"] {
+        if matches.value_source(option) == Some(ValueSource::CommandLine) {
+            anyhow::bail!("--{option} can't be used with {subcommand}");
+        }
+    }
+    Ok(())
+}
+
+/// The value of an argument, unless it's just the built-in default.
+fn explicit<'a, T: Clone + Send + Sync + 'static>(
+    matches: &'a ArgMatches,
+    id: &str,
+) -> Option<&'a T> {
+    match matches.value_source(id) {
+        Some(ValueSource::DefaultValue) | None => None,
+        Some(_) => matches.get_one::<T>(id),
+    }
+}
+
+/// The config file given with `--config`, or the default one if it exists.
+fn config_file(matches: &ArgMatches) -> anyhow::Result<config::ConfigFile> {
+    match matches.get_one::<PathBuf>("config") {
+        Some(path) => config::ConfigFile::load(path, true),
+        None => match config::ConfigFile::default_path() {
+            Some(path) => config::ConfigFile::load(&path, false),
+            None => Ok(config::ConfigFile::default()),
+        },
+    }
+}
+
+/// Closes a connection whose work is done. By then the results have been
+/// printed, so a failed closing handshake is only reported.
+fn close(client: &mut Client) {
+    if let Err(error) = client.close(Some(normal_close())) {
+        eprintln!("Failed to close the connection: {error}");
+    }
+}
+
+fn normal_close() -> CloseFrame {
+    CloseFrame {
+        code: CloseCode::Normal,
+        reason: "".into(),
+    }
 }
 
 mod style {
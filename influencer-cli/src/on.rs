@@ -0,0 +1,165 @@
+//! `influencer on`: runs a command for each matching event.
+//!
+//! The command gets the event as JSON on stdin, and these environment
+//! variables:
+//!
+//! - `OBS_EVENT_TYPE` and `OBS_EVENT_INTENT`
+//! - `OBS_EVENT_DATA`: `eventData` as JSON
+//! - `OBS_EVENT_DATA_<FIELD>` for each field of `eventData`, named in upper
+//!   snake case (`sceneName` is `OBS_EVENT_DATA_SCENE_NAME`, `inputUUID` is
+//!   `OBS_EVENT_DATA_INPUT_UUID`). Strings are passed as they are, anything
+//!   else as JSON.
+
+use crate::filter::EventFilter;
+
+use influencer::{
+    client::Client,
+    message::{self as m, WsMessageExt as _},
+};
+use serde_json::Value;
+use std::{
+    collections::VecDeque,
+    io::Write as _,
+    process::{Child, Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+pub struct Options {
+    /// Program and arguments.
+    pub command: Vec<String>,
+    /// How many commands may run at once. Events wait in line for a slot.
+    pub concurrency: usize,
+    /// Only run for the last of a burst of events, once none has matched
+    /// for this long.
+    pub debounce: Option<Duration>,
+}
+
+/// An event ready to be handed to the command.
+struct Job {
+    json: String,
+    env: Vec<(String, String)>,
+}
+impl Job {
+    fn new(event: &m::AnyEvent) -> anyhow::Result<Self> {
+        let mut env = Vec::new();
+        if let Some(Value::Object(ref fields)) = event.event_data {
+            for (name, value) in fields {
+                let value = match value {
+                    Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                env.push((format!("OBS_EVENT_DATA_{}", upper_snake_case(name)), value));
+            }
+        }
+        if let Some(ref data) = event.event_data {
+            env.push(("OBS_EVENT_DATA".to_owned(), data.to_string()));
+        }
+        env.push(("OBS_EVENT_TYPE".to_owned(), event.event_type.to_owned()));
+        env.push((
+            "OBS_EVENT_INTENT".to_owned(),
+            event.event_intent.to_string(),
+        ));
+        Ok(Self {
+            json: serde_json::to_string(event)?,
+            env,
+        })
+    }
+    fn spawn(self, command: &[String]) -> std::io::Result<Child> {
+        let mut child = Command::new(&command[0])
+            .args(&command[1..])
+            .envs(self.env)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        // The command doesn't have to read it, and mustn't hold us up
+        thread::spawn(move || {
+            let _ = writeln!(stdin, "{}", self.json);
+        });
+        Ok(child)
+    }
+}
+
+/// Runs until `interrupted` is set, then waits for running commands.
+pub fn run(
+    client: &mut Client,
+    filter: &EventFilter,
+    options: &Options,
+    interrupted: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut running: Vec<Child> = Vec::new();
+    let mut queue = VecDeque::new();
+    let mut debounced: Option<(Instant, Job)> = None;
+    while !interrupted.load(Ordering::Relaxed) {
+        if let Some(message) = client.try_read()? {
+            let event = message.obs_message_data::<m::AnyEvent>()?;
+            if filter.matches(&event) {
+                let job = Job::new(&event)?;
+                match options.debounce {
+                    Some(_) => debounced = Some((Instant::now(), job)),
+                    None => queue.push_back(job),
+                }
+            }
+        }
+        if let (Some(debounce), Some((received, _))) = (options.debounce, &debounced)
+            && received.elapsed() >= debounce
+        {
+            queue.extend(debounced.take().map(|(_, job)| job));
+        }
+        running.retain_mut(|child| !finished(child));
+        while running.len() < options.concurrency
+            && let Some(job) = queue.pop_front()
+        {
+            match job.spawn(&options.command) {
+                Ok(child) => running.push(child),
+                Err(error) => eprintln!("Failed to run {}: {error}", options.command[0]),
+            }
+        }
+    }
+    for mut child in running {
+        report(child.wait());
+    }
+    Ok(())
+}
+
+/// Whether the command has exited, reporting it if it failed.
+fn finished(child: &mut Child) -> bool {
+    match child.try_wait() {
+        Ok(None) => false,
+        Ok(Some(status)) => {
+            report(Ok(status));
+            true
+        }
+        Err(error) => {
+            report(Err(error));
+            true
+        }
+    }
+}
+
+fn report(status: std::io::Result<std::process::ExitStatus>) {
+    match status {
+        Ok(status) if !status.success() => eprintln!("Command failed: {status}"),
+        Ok(_) => {}
+        Err(error) => eprintln!("Failed to wait for command: {error}"),
+    }
+}
+
+/// `sceneItemId` to `SCENE_ITEM_ID`. A run of capitals is one word, so
+/// `inputUUID` is `INPUT_UUID` and `UUIDList` is `UUID_LIST`.
+fn upper_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut out = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let after_lower = !chars[i - 1].is_uppercase();
+            let ends_run = chars.get(i + 1).is_some_and(|next| next.is_lowercase());
+            if after_lower || ends_run {
+                out.push('_');
+            }
+        }
+        out.extend(c.to_uppercase());
+    }
+    out
+}
@@ -0,0 +1,418 @@
+//! How responses and events are printed: the `--output` formats, and the
+//! part of them chosen with `--field`.
+
+use crate::{filter::path_to_pointer, json_serialize};
+
+use clap::{
+    ValueEnum,
+    builder::{
+        PossibleValue,
+        styling::{AnsiColor, Effects, Style},
+    },
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write as _,
+    io::{IsTerminal as _, Write as _},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// JSON, pretty unless `--compact` is given.
+    Json,
+    /// Strings without quotes, anything else as JSON.
+    Raw,
+    Yaml,
+    /// Arrays of objects as rows of tab-separated values, with a header.
+    Tsv,
+    /// Like `Tsv`, but comma-separated.
+    Csv,
+    /// Human-readable, in color on terminals.
+    Summary,
+}
+impl ValueEnum for Format {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::Json,
+            Self::Raw,
+            Self::Yaml,
+            Self::Tsv,
+            Self::Csv,
+            Self::Summary,
+        ]
+    }
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Json => PossibleValue::new("json").help("Pretty unless --compact is given"),
+            Self::Raw => PossibleValue::new("raw").help("Strings without quotes"),
+            Self::Yaml => PossibleValue::new("yaml"),
+            Self::Tsv => PossibleValue::new("tsv").help("Tab-separated rows, for arrays"),
+            Self::Csv => PossibleValue::new("csv").help("Comma-separated rows, for arrays"),
+            Self::Summary => PossibleValue::new("summary").help("Human-readable"),
+        })
+    }
+}
+
+/// `--field`: a path like `responseData.outputActive` or `.scenes[0]`, or a
+/// JSON pointer like `/responseData/outputActive`. Returns the pointer.
+pub fn parse_field(arg: &str) -> Result<String, String> {
+    if arg.is_empty() || arg.starts_with('/') {
+        Ok(arg.to_owned())
+    } else if arg.starts_with('.') {
+        path_to_pointer(arg)
+    } else {
+        path_to_pointer(&format!(".{arg}"))
+    }
+}
+
+pub struct Output {
+    format: Format,
+    pretty: bool,
+    /// JSON pointer to the part to print.
+    field: Option<String>,
+    color: bool,
+    /// Whether anything has been printed, for YAML document separators.
+    printed: Cell<bool>,
+    /// The header of the last table printed, so that a stream of rows with
+    /// the same columns gets only one.
+    columns: RefCell<Option<Vec<String>>>,
+}
+impl Output {
+    pub fn new(format: Format, pretty: bool, field: Option<String>) -> Self {
+        Self {
+            format,
+            pretty,
+            field,
+            color: std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+            printed: Cell::new(false),
+            columns: RefCell::new(None),
+        }
+    }
+    pub fn print<T: Serialize>(&self, data: &T) -> anyhow::Result<()> {
+        let mut value = serde_json::to_value(data)?;
+        if let Some(ref pointer) = self.field {
+            value = value.pointer(pointer).cloned().unwrap_or(Value::Null);
+        }
+        let mut text = String::new();
+        match self.format {
+            Format::Json => {
+                let mut json = Vec::new();
+                json_serialize(self.pretty, &value, &mut json)?;
+                text = String::from_utf8(json)?;
+                text.push('\n');
+            }
+            Format::Raw => match value {
+                Value::String(string) => writeln!(text, "{string}")?,
+                value => {
+                    let mut json = Vec::new();
+                    json_serialize(self.pretty, &value, &mut json)?;
+                    writeln!(text, "{}", String::from_utf8(json)?)?;
+                }
+            },
+            Format::Yaml => {
+                if self.printed.replace(true) {
+                    text.push_str("---\n");
+                }
+                text.push_str(&serde_norway::to_string(&value)?);
+            }
+            Format::Tsv | Format::Csv => {
+                let (columns, rows) = table(&value);
+                let cell = match self.format {
+                    Format::Tsv => tsv_cell,
+                    _ => csv_cell,
+                };
+                let separator = if self.format == Format::Tsv {
+                    "\t"
+                } else {
+                    ","
+                };
+                let mut line = |cells: &[String]| {
+                    let cells: Vec<String> = cells.iter().map(|value| cell(value)).collect();
+                    text.push_str(&cells.join(separator));
+                    text.push('\n');
+                };
+                if columns.is_some() && *self.columns.borrow() != columns {
+                    line(columns.as_deref().unwrap());
+                    *self.columns.borrow_mut() = columns;
+                }
+                for row in rows {
+                    line(&row);
+                }
+            }
+            Format::Summary => summary(&value, self.color, &mut text),
+        }
+        let mut out = std::io::stdout().lock();
+        out.write_all(text.as_bytes())?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => items.is_empty(),
+        Value::Object(fields) => fields.is_empty(),
+        _ => true,
+    }
+}
+
+/// The header, if there is one, and rows of `value`. Arrays of objects are
+/// rows with a column per key. Responses and events are looked into for
+/// their data, and objects for an only array field.
+fn table(value: &Value) -> (Option<Vec<String>>, Vec<Vec<String>>) {
+    match value {
+        Value::Array(items) if items.iter().all(Value::is_object) && !items.is_empty() => {
+            let mut columns: Vec<String> = Vec::new();
+            for item in items.iter().filter_map(Value::as_object) {
+                for key in item.keys() {
+                    if !columns.contains(key) {
+                        columns.push(key.clone());
+                    }
+                }
+            }
+            let rows = items
+                .iter()
+                .map(|item| columns.iter().map(|key| cell(item.get(key))).collect())
+                .collect();
+            (Some(columns), rows)
+        }
+        Value::Array(items) => (
+            None,
+            items.iter().map(|item| vec![cell(Some(item))]).collect(),
+        ),
+        Value::Object(fields) => {
+            let data = fields
+                .get("responseData")
+                .or_else(|| fields.get("eventData"));
+            let mut arrays = fields.values().filter(|value| value.is_array());
+            match (data, arrays.next(), arrays.next()) {
+                (Some(data), _, _) => table(data),
+                (None, Some(array), None) => table(array),
+                _ => (
+                    Some(fields.keys().cloned().collect()),
+                    vec![fields.values().map(Some).map(cell).collect()],
+                ),
+            }
+        }
+        value => (None, vec![vec![cell(Some(value))]]),
+    }
+}
+
+/// Strings as they are, nothing for null, anything else as JSON.
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(string)) => string.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn tsv_cell(cell: &str) -> String {
+    cell.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn csv_cell(cell: &str) -> String {
+    match cell.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", cell.replace('"', "\"\"")),
+        false => cell.to_owned(),
+    }
+}
+
+const KEY: Style = AnsiColor::Cyan.on_default();
+const LITERAL: Style = AnsiColor::Yellow.on_default();
+const DIM: Style = Style::new().effects(Effects::DIMMED);
+const TITLE: Style = Style::new().effects(Effects::BOLD);
+const SUCCESS: Style = AnsiColor::Green.on_default().effects(Effects::BOLD);
+const FAILURE: Style = AnsiColor::Red.on_default().effects(Effects::BOLD);
+
+fn paint(color: bool, style: Style, text: &str) -> String {
+    match color {
+        true => format!("{style}{text}{style:#}"),
+        false => text.to_owned(),
+    }
+}
+
+/// A line for each response or event, followed by its data.
+fn summary(value: &Value, color: bool, out: &mut String) {
+    let text = |key: &str| value.get(key).and_then(Value::as_str);
+    if let (Some(request_type), Some(status)) = (text("requestType"), value.get("requestStatus")) {
+        if status["result"] == true {
+            out.push_str(&paint(color, SUCCESS, "✓"));
+            out.push_str(&format!(" {}\n", paint(color, TITLE, request_type)));
+        } else {
+            out.push_str(&paint(color, FAILURE, "✗"));
+            out.push_str(&format!(
+                " {} failed with code {}: {}\n",
+                paint(color, TITLE, request_type),
+                status["code"],
+                status["comment"].as_str().unwrap_or("no comment")
+            ));
+        }
+        tree(&value["responseData"], 2, color, out);
+    } else if let Some(event_type) = text("eventType") {
+        out.push_str(&paint(color, TITLE, event_type));
+        if let Some(received_at) = text("receivedAt") {
+            out.push_str(&format!(" {}", paint(color, DIM, received_at)));
+        }
+        out.push('\n');
+        tree(&value["eventData"], 2, color, out);
+    } else if let Some(results) = value.get("results").and_then(Value::as_array) {
+        for result in results {
+            summary(result, color, out);
+        }
+    } else {
+        tree(value, 0, color, out);
+    }
+}
+
+fn tree(value: &Value, indent: usize, color: bool, out: &mut String) {
+    let pad = " ".repeat(indent);
+    match value {
+        Value::Null => {}
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                let key = paint(color, KEY, key);
+                if is_scalar(value) {
+                    out.push_str(&format!("{pad}{key}: {}\n", scalar(value, color)));
+                } else {
+                    out.push_str(&format!("{pad}{key}:\n"));
+                    tree(value, indent + 2, color, out);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                if is_scalar(item) {
+                    out.push_str(&format!("{pad}- {}\n", scalar(item, color)));
+                } else {
+                    let mut nested = String::new();
+                    tree(item, indent + 2, color, &mut nested);
+                    out.push_str(&format!("{pad}- {}", &nested[indent + 2..]));
+                }
+            }
+        }
+        value => out.push_str(&format!("{pad}{}\n", scalar(value, color))),
+    }
+}
+
+fn scalar(value: &Value, color: bool) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        Value::Null => paint(color, DIM, "null"),
+        Value::Array(_) => paint(color, DIM, "(none)"),
+        Value::Object(_) => paint(color, DIM, "(empty)"),
+        value => paint(color, LITERAL, &value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|&s| s.to_owned()).collect()
+    }
+
+    #[test]
+    fn yaml_strings_read_back() {
+        let strings = json!([
+            "Scene",
+            "",
+            "- item",
+            "-1",
+            "? key",
+            "*alias",
+            "!tag",
+            "key: value",
+            "a #comment",
+            "true",
+            "No",
+            "on",
+            "NULL",
+            "42",
+            "1.5",
+            " lead",
+            "trail ",
+            "two\nlines",
+            "tab\there",
+            "🎤 Mic",
+            "say \"hi\"",
+        ]);
+        let yaml = serde_norway::to_string(&strings).unwrap();
+        assert_eq!(serde_norway::from_str::<Value>(&yaml).unwrap(), strings);
+    }
+
+    #[test]
+    fn yaml_documents() {
+        let value = json!({
+            "Größe": 1,
+            "empty": {"array": [], "object": {}},
+            "items": [[], {}, {"a": []}, [1, {"b": null}]],
+        });
+        assert_eq!(
+            serde_norway::to_string(&value).unwrap(),
+            concat!(
+                "Größe: 1\n",
+                "empty:\n",
+                "  array: []\n",
+                "  object: {}\n",
+                "items:\n",
+                "- []\n",
+                "- {}\n",
+                "- a: []\n",
+                "- - 1\n",
+                "  - b: null\n",
+            )
+        );
+    }
+
+    #[test]
+    fn table_of_objects() {
+        let (columns, rows) = table(&json!([
+            {"a": 1, "b": "x"},
+            {"b": null, "c": [1]},
+        ]));
+        assert_eq!(columns, Some(strings(&["a", "b", "c"])));
+        assert_eq!(rows, [strings(&["1", "x", ""]), strings(&["", "", "[1]"])]);
+    }
+
+    #[test]
+    fn table_of_response() {
+        let response = json!({
+            "requestType": "GetInputList",
+            "requestStatus": {"result": true, "code": 100},
+            "responseData": {"inputs": [{"inputName": "Mic", "inputKind": "pulse"}]},
+        });
+        let (columns, rows) = table(&response);
+        assert_eq!(columns, Some(strings(&["inputKind", "inputName"])));
+        assert_eq!(rows, [strings(&["pulse", "Mic"])]);
+
+        // Without an only array, the object is one row
+        let (columns, rows) = table(&json!({"a": [1], "b": [], "c": {}}));
+        assert_eq!(columns, Some(strings(&["a", "b", "c"])));
+        assert_eq!(rows, [strings(&["[1]", "[]", "{}"])]);
+    }
+
+    #[test]
+    fn table_of_scalars() {
+        let (columns, rows) = table(&json!(["a", 1, null, {"x": 1}]));
+        assert_eq!(columns, None);
+        assert_eq!(
+            rows,
+            [
+                strings(&["a"]),
+                strings(&["1"]),
+                strings(&[""]),
+                strings(&[r#"{"x":1}"#])
+            ]
+        );
+        assert_eq!(table(&json!([])), (None, vec![]));
+        assert_eq!(table(&json!("x")), (None, vec![strings(&["x"])]));
+    }
+}
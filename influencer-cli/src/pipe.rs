@@ -0,0 +1,159 @@
+//! `influencer pipe`: requests and batches as NDJSON on stdin, responses and
+//! events as NDJSON on stdout, over one connection.
+//!
+//! Input lines use the JSON shapes of the `request` and `batch` commands; a
+//! line with a `requests` array is a batch. Responses carry the request id
+//! given in the input, or the line number if there was none. Lines that
+//! can't be sent are answered with `{"line": N, "error": "..."}`, and so are
+//! requests still unanswered a while after stdin is closed.
+
+use crate::{json_serialize, session::Session};
+
+use influencer::{
+    client,
+    message::{self as m, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _},
+};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::{
+    io::{BufRead as _, Write as _, stdin, stdout},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+use tungstenite::Message as WsMessage;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct RequestLine {
+    request_type: String,
+    request_id: Option<String>,
+    request_data: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct BatchLine {
+    request_id: Option<String>,
+    halt_on_failure: Option<bool>,
+    execution_type: Option<i32>,
+    requests: Vec<Value>,
+}
+
+/// Runs until stdin is closed and every request has been answered, or
+/// `timeout` has passed since.
+pub fn run(config: client::Config, timeout: Duration) -> anyhow::Result<()> {
+    let (output, messages) = mpsc::channel();
+    let (answered, answers) = mpsc::channel();
+    let session = Session::connect(config, Some(output.clone()))?;
+    let writer = thread::spawn(move || {
+        for message in messages {
+            if let Some(request_id) = write_message(&message)
+                && answered.send(request_id).is_err()
+            {
+                break;
+            }
+        }
+    });
+    // Request ids and line numbers of the requests sent so far
+    let mut pending = Vec::new();
+    for (i, line) in stdin().lock().lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (message, request_id) = match parse_line(&line, i + 1) {
+            Ok(parsed) => parsed,
+            Err(error) => {
+                write_json(&json!({"line": i + 1, "error": format!("{error:#}")}));
+                continue;
+            }
+        };
+        if session.send(message, output.clone()).is_err() {
+            break;
+        }
+        pending.push((request_id, i + 1));
+    }
+    // Once the session ends, the writer does too, and stops answering
+    drop(output);
+    let deadline = Instant::now() + timeout;
+    while !pending.is_empty() {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let Ok(request_id) = answers.recv_timeout(timeout) else {
+            break;
+        };
+        if let Some(i) = pending.iter().position(|(id, _)| *id == request_id) {
+            pending.remove(i);
+        }
+    }
+    for (_, line) in &pending {
+        write_json(&json!({"line": line, "error": "No response from OBS"}));
+    }
+    let result = session.close();
+    let _ = writer.join();
+    if !pending.is_empty() {
+        anyhow::bail!("{} request(s) never got a response", pending.len());
+    }
+    result
+}
+
+/// The message for a line, and its request id.
+fn parse_line(line: &str, number: usize) -> anyhow::Result<(WsMessage, String)> {
+    let value: Value = serde_json::from_str(line)?;
+    let default_id = number.to_string();
+    if value.get("requests").is_some() {
+        let batch: BatchLine = serde_json::from_value(value)?;
+        let request_id = batch.request_id.unwrap_or(default_id);
+        let request = m::RequestBatch {
+            request_id: &request_id,
+            halt_on_failure: batch.halt_on_failure,
+            execution_type: batch.execution_type,
+            requests: batch.requests,
+        };
+        Ok((request.into_ws_message_json()?, request_id))
+    } else {
+        let request: RequestLine = serde_json::from_value(value)?;
+        let request_id = request.request_id.unwrap_or(default_id);
+        let request = m::Request {
+            request_type: &request.request_type,
+            request_id: &request_id,
+            request_data: request.request_data,
+        };
+        Ok((request.into_ws_message_json()?, request_id))
+    }
+}
+
+/// Writes a response or event. Returns the request id if it was a response.
+fn write_message(message: &WsMessage) -> Option<String> {
+    match message.any_obs_server_message() {
+        Ok(ServerMessage::Event(_)) => {
+            if let Ok(event) = message.obs_message_data::<m::AnyEvent>() {
+                write_json(&event);
+            }
+            None
+        }
+        Ok(ServerMessage::Response(info)) => {
+            if let Ok(response) = message.obs_message_data::<m::AnyResponse>() {
+                write_json(&response);
+            }
+            Some(info.request_id.to_owned())
+        }
+        Ok(ServerMessage::ResponseBatch(info)) => {
+            if let Ok(response) = message.obs_message_data::<m::AnyResponseBatch>() {
+                write_json(&response);
+            }
+            Some(info.request_id.to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Writes one line to stdout, and flushes it right away for the benefit
+/// of whoever is reading.
+fn write_json<T: serde::Serialize>(data: &T) {
+    let mut out = stdout().lock();
+    if json_serialize(false, data, &mut out).is_ok() {
+        let _ = writeln!(out);
+        let _ = out.flush();
+    }
+}
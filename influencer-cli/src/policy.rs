@@ -0,0 +1,116 @@
+//! Access control for `influencer proxy`: which requests clients may send.
+//!
+//! A policy is a TOML file with a list of rules. The first rule matching a
+//! request decides whether it is allowed; if none matches, `default` does.
+//!
+//! ```toml
+//! default = "deny"
+//!
+//! [[rule]]
+//! action = "allow"
+//! requests = ["Get*"]
+//!
+//! [[rule]]
+//! action = "allow"
+//! requests = ["SetCurrentProgramScene"]
+//! fields.sceneName = "Game *"
+//! ```
+//!
+//! Request type and field patterns are globs (`*` and `?`). Field names are
+//! keys of `requestData`, or JSON pointers if they start with `/`. Fields that
+//! aren't strings are matched against their JSON text.
+//!
+//! A field missing from a request matches deny rules, but not allow rules,
+//! so that leaving a field out (say, naming an input by `inputUuid` rather
+//! than `inputName`) never gets a request past a rule meant to stop it.
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{collections::HashMap, path::Path};
+use wildmatch::WildMatch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Deny,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    action: Action,
+    #[serde(deserialize_with = "patterns")]
+    requests: Vec<WildMatch>,
+    #[serde(default, deserialize_with = "field_patterns")]
+    fields: HashMap<String, WildMatch>,
+}
+impl Rule {
+    fn matches(&self, request_type: &str, request_data: Option<&Value>) -> bool {
+        self.requests
+            .iter()
+            .any(|pattern| pattern.matches(request_type))
+            && self.fields.iter().all(|(field, pattern)| {
+                let value = match field.starts_with('/') {
+                    true => request_data.and_then(|data| data.pointer(field)),
+                    false => request_data.and_then(|data| data.get(field)),
+                };
+                match value {
+                    Some(Value::String(value)) => pattern.matches(value),
+                    Some(value) => pattern.matches(&value.to_string()),
+                    None => self.action == Action::Deny,
+                }
+            })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    default: Action,
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+impl Default for Policy {
+    /// Allows everything.
+    fn default() -> Self {
+        Self {
+            default: Action::Allow,
+            rules: Vec::new(),
+        }
+    }
+}
+impl Policy {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read policy {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("Invalid policy {}", path.display()))
+    }
+    pub fn allows(&self, request_type: &str, request_data: Option<&Value>) -> bool {
+        let action = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(request_type, request_data))
+            .map_or(self.default, |rule| rule.action);
+        action == Action::Allow
+    }
+}
+
+fn patterns<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<WildMatch>, D::Error> {
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    Ok(patterns
+        .iter()
+        .map(|pattern| WildMatch::new(pattern))
+        .collect())
+}
+
+fn field_patterns<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, WildMatch>, D::Error> {
+    let patterns = HashMap::<String, String>::deserialize(deserializer)?;
+    Ok(patterns
+        .into_iter()
+        .map(|(field, pattern)| (field, WildMatch::new(&pattern)))
+        .collect())
+}
@@ -0,0 +1,226 @@
+//! The obs-websocket 5.x protocol reference, for `list`, `describe`,
+//! completion and event subscriptions.
+//!
+//! `protocol.json` follows the schema of obs-websocket's
+//! `docs/generated/protocol.json`, but isn't that file: it's assembled from
+//! the request, response and event definitions and doc comments of obws
+//! 0.14.0 (MIT, see `protocol.json.LICENSE`), and pinned to that version. So
+//! every entry says RPC version 1 and isn't deprecated.
+//!
+//! The upstream file can replace it as is, pinned to an obs-websocket
+//! release tag here. It's GPL-2.0 licensed, so its license has to ship with
+//! it.
+
+use influencer::message::event_subscription::*;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Write as _, sync::LazyLock};
+use wildmatch::WildMatch;
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Field {
+    pub value_name: String,
+    pub value_type: String,
+    pub value_description: String,
+    #[serde(default)]
+    pub value_optional: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub request_type: String,
+    pub description: String,
+    pub rpc_version: String,
+    pub deprecated: bool,
+    pub category: String,
+    pub request_fields: Vec<Field>,
+    pub response_fields: Vec<Field>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Event {
+    pub event_type: String,
+    pub event_subscription: String,
+    pub description: String,
+    pub rpc_version: String,
+    pub deprecated: bool,
+    pub category: String,
+    pub data_fields: Vec<Field>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Protocol {
+    /// Sorted by type.
+    pub requests: Vec<Request>,
+    /// Sorted by type.
+    pub events: Vec<Event>,
+}
+
+pub static PROTOCOL: LazyLock<Protocol> = LazyLock::new(|| {
+    let mut protocol: Protocol = serde_json::from_str(include_str!("../protocol.json"))
+        .expect("embedded protocol.json is valid");
+    protocol
+        .requests
+        .sort_by(|a, b| a.request_type.cmp(&b.request_type));
+    protocol
+        .events
+        .sort_by(|a, b| a.event_type.cmp(&b.event_type));
+    protocol
+});
+
+/// A request or event type.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Entry {
+    Request(&'static Request),
+    Event(&'static Event),
+}
+
+/// Looks up a request or event type, ignoring case if there's no exact
+/// match.
+pub fn find(name: &str) -> Option<Entry> {
+    let requests = PROTOCOL
+        .requests
+        .iter()
+        .map(|r| (&r.request_type, Entry::Request(r)));
+    let events = PROTOCOL
+        .events
+        .iter()
+        .map(|e| (&e.event_type, Entry::Event(e)));
+    let mut inexact = None;
+    for (type_, entry) in requests.chain(events) {
+        if type_ == name {
+            return Some(entry);
+        }
+        if inexact.is_none() && type_.eq_ignore_ascii_case(name) {
+            inexact = Some(entry);
+        }
+    }
+    inexact
+}
+
+/// The bit of a subscription category, like `InputVolumeMeters`.
+pub fn subscription(name: &str) -> Option<u32> {
+    Some(match name {
+        "General" => GENERAL,
+        "Config" => CONFIG,
+        "Scenes" => SCENES,
+        "Inputs" => INPUTS,
+        "Transitions" => TRANSITIONS,
+        "Filters" => FILTERS,
+        "Outputs" => OUTPUTS,
+        "SceneItems" => SCENE_ITEMS,
+        "MediaInputs" => MEDIA_INPUTS,
+        "Vendors" => VENDORS,
+        "Ui" => UI,
+        "InputVolumeMeters" => INPUT_VOLUME_METERS,
+        "InputActiveStateChanged" => INPUT_ACTIVE_STATE_CHANGED,
+        "InputShowStateChanged" => INPUT_SHOW_STATE_CHANGED,
+        "SceneItemTransformChanged" => SCENE_ITEM_TRANSFORM_CHANGED,
+        _ => return None,
+    })
+}
+
+/// The subscriptions needed to receive all events matching any of
+/// `patterns`, or `None` if no known event type matches.
+///
+/// High-volume events, like `InputVolumeMeters`, only count if a pattern
+/// names them without wildcards, so `*` doesn't flood the connection.
+pub fn event_subscriptions(patterns: &[WildMatch]) -> Option<u32> {
+    PROTOCOL
+        .events
+        .iter()
+        .filter_map(|event| {
+            let bit = subscription(&event.event_subscription)?;
+            let high_volume = bit & ALL == 0;
+            patterns
+                .iter()
+                .any(|pattern| match high_volume {
+                    true => pattern.to_string() == event.event_type,
+                    false => pattern.matches(&event.event_type),
+                })
+                .then_some(bit)
+        })
+        .reduce(|a, b| a | b)
+}
+
+/// The first line of a description, for lists.
+pub fn summary(description: &str) -> &str {
+    description.lines().next().unwrap_or_default()
+}
+
+/// A human-readable description of `entry`.
+pub fn describe(entry: &Entry) -> String {
+    let mut text = String::new();
+    let (name, kind, description, rpc_version, deprecated, category) = match *entry {
+        Entry::Request(r) => (
+            &r.request_type,
+            "request",
+            &r.description,
+            &r.rpc_version,
+            r.deprecated,
+            &r.category,
+        ),
+        Entry::Event(e) => (
+            &e.event_type,
+            "event",
+            &e.description,
+            &e.rpc_version,
+            e.deprecated,
+            &e.category,
+        ),
+    };
+    let _ = writeln!(text, "{name} ({kind}, {category})");
+    if deprecated {
+        let _ = writeln!(text, "Deprecated");
+    }
+    let _ = writeln!(text, "\n{description}\n");
+    let _ = writeln!(text, "RPC version: {rpc_version}");
+    match *entry {
+        Entry::Request(r) => {
+            write_fields(&mut text, "Request fields", &r.request_fields);
+            if !r.response_fields.is_empty() {
+                write_fields(&mut text, "Response fields", &r.response_fields);
+            }
+        }
+        Entry::Event(e) => {
+            let _ = match subscription(&e.event_subscription) {
+                Some(bit) => writeln!(
+                    text,
+                    "Subscription: {} (1 << {})",
+                    e.event_subscription,
+                    bit.trailing_zeros()
+                ),
+                None => writeln!(text, "Subscription: {}", e.event_subscription),
+            };
+            write_fields(&mut text, "Data fields", &e.data_fields);
+        }
+    }
+    text
+}
+
+fn write_fields(text: &mut String, title: &str, fields: &[Field]) {
+    if fields.is_empty() {
+        let _ = writeln!(text, "{title}: none");
+        return;
+    }
+    let _ = writeln!(text, "{title}:");
+    let name_width = fields.iter().map(|f| f.value_name.len()).max().unwrap_or(0);
+    let type_width = fields.iter().map(|f| f.value_type.len()).max().unwrap_or(0);
+    // Only leave room for "optional" if some field is
+    let optional_width = if fields.iter().any(|f| f.value_optional) {
+        10
+    } else {
+        0
+    };
+    for field in fields {
+        let optional = if field.value_optional { "optional" } else { "" };
+        let line = format!(
+            "  {:name_width$}  {:type_width$}  {optional:optional_width$}{}",
+            field.value_name, field.value_type, field.value_description
+        );
+        let _ = writeln!(text, "{}", line.trim_end());
+    }
+}
@@ -0,0 +1,388 @@
+//! `influencer proxy`: one authenticated upstream connection to OBS, shared
+//! by any number of downstream obs-websocket clients.
+//!
+//! Request ids are rewritten on the way upstream so that responses can be
+//! routed back to the client that sent the request, with the original id
+//! restored. Events are forwarded to every client whose `eventSubscriptions`
+//! match, and the upstream connection subscribes to the union of those.
+//! Requests the [`Policy`] doesn't allow are answered by the proxy itself
+//! with a failed `requestStatus`, and never reach OBS. So are requests whose
+//! responses were lost because the upstream connection had to be
+//! re-established.
+
+use crate::{
+    policy::Policy,
+    session::{LOST, failed, failed_batch, failed_response, with_request_id},
+};
+
+use anyhow::Context as _;
+use influencer::{
+    auth::MessageStream as _,
+    client::{self, Client, HANDSHAKE_TIMEOUT},
+    message::{
+        self as m, ClientMessage, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _,
+        close_code,
+    },
+    server::ServerAuth,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    io::ErrorKind as IoErrorKind,
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+use tungstenite::{
+    Error as WsError, Message as WsMessage, WebSocket,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+/// How long either side waits for a message before checking its queue.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long to wait for a client to confirm a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The `requestStatus` comment for requests the policy doesn't allow.
+const DENIED: &str = "Denied by proxy policy.";
+/// The `requestStatus` comment for allowed requests in a denied batch.
+const BATCH_DENIED: &str = "Not executed, another request in the batch was denied by proxy policy.";
+
+/// Work for the thread owning the upstream connection.
+enum Upstream {
+    Send {
+        request_id: String,
+        message: WsMessage,
+    },
+    /// The clients' event subscriptions changed.
+    Resubscribe,
+}
+
+struct Downstream {
+    outgoing: Sender<WsMessage>,
+    event_subscriptions: u32,
+}
+
+/// Where to send the response to a rewritten request id.
+struct Route {
+    client: u64,
+    request_id: String,
+    /// The client's request or batch, to answer it if OBS never will.
+    message: WsMessage,
+    /// The upstream connection it was sent on, counting reconnects.
+    connection: Option<u64>,
+}
+
+struct Hub {
+    downstreams: Mutex<HashMap<u64, Downstream>>,
+    /// Keyed by the request id sent upstream.
+    routes: Mutex<HashMap<String, Route>>,
+    next_id: AtomicU64,
+    upstream: Sender<Upstream>,
+    policy: Policy,
+}
+impl Hub {
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+    fn event_subscriptions(&self) -> u32 {
+        let downstreams = self.downstreams.lock().unwrap();
+        downstreams
+            .values()
+            .fold(0, |subs, downstream| subs | downstream.event_subscriptions)
+    }
+    fn resubscribe(&self) {
+        let _ = self.upstream.send(Upstream::Resubscribe);
+    }
+    /// Sends a client's request or batch upstream under a fresh request id.
+    fn forward(&self, client: u64, request_id: &str, message: &WsMessage) -> anyhow::Result<()> {
+        let upstream_id = self.next_id().to_string();
+        let upstream_message = with_request_id(message, &upstream_id)?;
+        self.routes.lock().unwrap().insert(
+            upstream_id.clone(),
+            Route {
+                client,
+                request_id: request_id.to_owned(),
+                message: message.clone(),
+                connection: None,
+            },
+        );
+        let _ = self.upstream.send(Upstream::Send {
+            request_id: upstream_id,
+            message: upstream_message,
+        });
+        Ok(())
+    }
+    fn sent(&self, upstream_id: &str, connection: u64) {
+        if let Some(route) = self.routes.lock().unwrap().get_mut(upstream_id) {
+            route.connection = Some(connection);
+        }
+    }
+    /// Answers the requests sent on connections before `connection`, whose
+    /// responses will never arrive.
+    fn fail_lost(&self, connection: u64) {
+        let mut lost = Vec::new();
+        self.routes.lock().unwrap().retain(|_, route| {
+            let keep = route.connection.is_none_or(|c| c >= connection);
+            if !keep {
+                lost.push((route.client, route.message.clone()));
+            }
+            keep
+        });
+        let downstreams = self.downstreams.lock().unwrap();
+        for (client, message) in lost {
+            let Some(downstream) = downstreams.get(&client) else {
+                continue;
+            };
+            match failed_response(&message, LOST) {
+                Ok(response) => {
+                    let _ = downstream.outgoing.send(response);
+                }
+                Err(error) => eprintln!("Failed to answer a lost request: {error}"),
+            }
+        }
+    }
+    /// Hands a message from OBS to the clients it concerns.
+    fn dispatch(&self, message: WsMessage) -> anyhow::Result<()> {
+        let request_id = match message.any_obs_server_message()? {
+            ServerMessage::Response(info) => info.request_id.to_owned(),
+            ServerMessage::ResponseBatch(info) => info.request_id.to_owned(),
+            ServerMessage::Event(info) => {
+                let downstreams = self.downstreams.lock().unwrap();
+                for downstream in downstreams.values() {
+                    if downstream.event_subscriptions & info.event_intent != 0 {
+                        let _ = downstream.outgoing.send(message.clone());
+                    }
+                }
+                return Ok(());
+            }
+            // Replies to our own Reidentify messages
+            ServerMessage::Hello(_) | ServerMessage::Identified(_) => return Ok(()),
+        };
+        let Some(route) = self.routes.lock().unwrap().remove(&request_id) else {
+            return Ok(());
+        };
+        let message = with_request_id(&message, &route.request_id)?;
+        if let Some(downstream) = self.downstreams.lock().unwrap().get(&route.client) {
+            let _ = downstream.outgoing.send(message);
+        }
+        Ok(())
+    }
+    fn remove(&self, client: u64) {
+        self.downstreams.lock().unwrap().remove(&client);
+        self.routes
+            .lock()
+            .unwrap()
+            .retain(|_, route| route.client != client);
+        self.resubscribe();
+    }
+}
+
+/// Connects upstream, then serves clients on `listen` until the upstream
+/// connection fails for good.
+pub fn run(
+    mut config: client::Config,
+    listen: &str,
+    password: Option<String>,
+    policy: Policy,
+) -> anyhow::Result<()> {
+    // Nobody is subscribed to anything yet
+    config.event_subscriptions = Some(0);
+    config.read_timeout = Some(POLL_INTERVAL);
+    let client = Client::connect(config)?;
+    let listener =
+        TcpListener::bind(listen).with_context(|| format!("Failed to listen on {listen}"))?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    let (upstream, commands) = mpsc::channel();
+    let hub = Arc::new(Hub {
+        downstreams: Mutex::new(HashMap::new()),
+        routes: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(0),
+        upstream,
+        policy,
+    });
+    let hub_ = hub.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let hub = hub_.clone();
+            let auth = ServerAuth::new(password.as_deref());
+            thread::spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(error) = downstream(&hub, stream, auth) {
+                    match peer {
+                        Ok(peer) => eprintln!("Client {peer}: {error}"),
+                        Err(_) => eprintln!("Client: {error}"),
+                    }
+                }
+            });
+        }
+    });
+    upstream_loop(&hub, client, commands)
+}
+
+fn upstream_loop(
+    hub: &Hub,
+    mut client: Client,
+    commands: Receiver<Upstream>,
+) -> anyhow::Result<()> {
+    let mut event_subscriptions = 0;
+    let mut connection = 0;
+    loop {
+        if client.reconnects() != connection {
+            connection = client.reconnects();
+            eprintln!("Reconnected to OBS");
+            hub.fail_lost(connection);
+        }
+        for command in commands.try_iter() {
+            match command {
+                Upstream::Send {
+                    request_id,
+                    message,
+                } => {
+                    client.send(message)?;
+                    hub.sent(&request_id, client.reconnects());
+                }
+                Upstream::Resubscribe => {
+                    let subs = hub.event_subscriptions();
+                    if subs != event_subscriptions {
+                        client.reidentify(Some(subs))?;
+                        event_subscriptions = subs;
+                    }
+                }
+            }
+        }
+        let Some(message) = client.try_read()? else {
+            continue;
+        };
+        if let Err(error) = hub.dispatch(message) {
+            eprintln!("Ignoring message from OBS: {error}");
+        }
+    }
+}
+
+fn downstream(hub: &Hub, stream: TcpStream, auth: ServerAuth) -> anyhow::Result<()> {
+    // Clients that never finish identifying don't get to keep a thread
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut ws = tungstenite::accept(stream).map_err(|error| anyhow::anyhow!("{error}"))?;
+    let identified = auth.handshake(&mut ws)?;
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    ws.get_ref().set_write_timeout(None)?;
+    let id = hub.next_id();
+    let (outgoing, incoming) = mpsc::channel();
+    hub.downstreams.lock().unwrap().insert(
+        id,
+        Downstream {
+            outgoing,
+            event_subscriptions: identified.event_subscriptions,
+        },
+    );
+    hub.resubscribe();
+    let result = serve(hub, id, identified.rpc_version, &mut ws, incoming);
+    hub.remove(id);
+    result
+}
+
+fn serve(
+    hub: &Hub,
+    id: u64,
+    rpc_version: u32,
+    ws: &mut WebSocket<TcpStream>,
+    incoming: Receiver<WsMessage>,
+) -> anyhow::Result<()> {
+    loop {
+        for message in incoming.try_iter() {
+            ws.write(message)?;
+        }
+        ws.flush()?;
+        let message = match ws.read() {
+            Ok(message @ WsMessage::Text(_)) => message,
+            Ok(_) => continue,
+            Err(WsError::Io(error))
+                if matches!(
+                    error.kind(),
+                    IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+                ) =>
+            {
+                continue;
+            }
+            Err(WsError::ConnectionClosed) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+        match message.any_obs_client_message() {
+            Ok(ClientMessage::Request(info)) => {
+                // A request OBS can't decode is denied, rather than
+                // trusting that OBS will reject it
+                let allowed =
+                    message
+                        .obs_message_data::<m::Request<Value>>()
+                        .is_ok_and(|request| {
+                            hub.policy
+                                .allows(info.request_type, request.request_data.as_ref())
+                        });
+                if allowed {
+                    hub.forward(id, info.request_id, &message)?;
+                } else {
+                    ws.write(failed(&info, DENIED)?)?;
+                }
+            }
+            Ok(ClientMessage::RequestBatch(info)) => {
+                let requests = message
+                    .obs_message_data::<m::RequestBatchVec<Value>>()
+                    .map(|batch| batch.requests)
+                    .unwrap_or_default();
+                let allowed: Vec<bool> = requests
+                    .iter()
+                    .map(|request| {
+                        hub.policy
+                            .allows(request.request_type, request.request_data.as_ref())
+                    })
+                    .collect();
+                // A batch OBS can't decode is denied too, rather than trusting
+                // that OBS will reject it.
+                if !requests.is_empty() && allowed.iter().all(|allowed| *allowed) {
+                    hub.forward(id, info.request_id, &message)?;
+                } else {
+                    let comments: Vec<&str> = allowed
+                        .iter()
+                        .map(|allowed| if *allowed { BATCH_DENIED } else { DENIED })
+                        .collect();
+                    ws.write(failed_batch(&info, &requests, &comments)?)?;
+                }
+            }
+            Ok(ClientMessage::Reidentify(reidentify)) => {
+                if let Some(subs) = reidentify.event_subscriptions {
+                    let mut downstreams = hub.downstreams.lock().unwrap();
+                    if let Some(downstream) = downstreams.get_mut(&id) {
+                        downstream.event_subscriptions = subs;
+                    }
+                }
+                hub.resubscribe();
+                let identified = m::Identified {
+                    negotiated_rpc_version: rpc_version,
+                };
+                ws.write(identified.into_ws_message_json()?)?;
+            }
+            Ok(ClientMessage::Identify(_)) => {
+                return close(ws, close_code::ALREADY_IDENTIFIED, "Already identified.");
+            }
+            Err(_) => {
+                return close(ws, close_code::MESSAGE_DECODE_ERROR, "Invalid message.");
+            }
+        }
+    }
+}
+
+fn close(ws: &mut WebSocket<TcpStream>, code: u16, reason: &str) -> anyhow::Result<()> {
+    ws.get_ref().set_read_timeout(Some(CLOSE_TIMEOUT))?;
+    let frame = CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.into(),
+    };
+    Ok(ws.close_handshake(Some(frame))?)
+}
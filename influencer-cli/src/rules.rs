@@ -0,0 +1,273 @@
+//! `influencer rules`: sends requests when events happen, as described by
+//! a rules file, in TOML or, for `.yaml` and `.yml` files, YAML with the
+//! same structure.
+//!
+//! ```toml
+//! [[rule]]
+//! name = "brb"
+//! when = "CurrentProgramSceneChanged"
+//! where = ['.sceneName == "BRB"']
+//! then = [
+//!     { request = "SetInputMute", data = { inputName = "Mic", inputMuted = true } },
+//!     { request = "StartReplayBuffer" },
+//! ]
+//!
+//! [[rule]]
+//! when = "InputMuteStateChanged"
+//! capture = { muted = ".inputMuted" }
+//! then = [
+//!     { request = "SetSourceFilterEnabled", data = { sourceName = "{inputName}", filterName = "Muted", filterEnabled = "{muted}" } },
+//! ]
+//! ```
+//!
+//! `when` is an event type glob and `where` a list of predicates, like the
+//! `--type` and `--where` options of `influencer events`. Every rule that
+//! matches an event fires, in order.
+//!
+//! Request data may contain `{name}` placeholders like macros do. Names are
+//! the top-level fields of `eventData` and the paths captured by `capture`,
+//! whose values keep their type when filling a whole string, like
+//! `"{muted}"` above.
+//! A rule with one request sends it on its own; one with several sends them
+//! as a batch, stopping at the first failure if `halt_on_failure` is set.
+
+use crate::{
+    config::{self, fill_placeholders},
+    filter::{EventFilter, Predicate, path_to_pointer},
+    output::Output,
+    session,
+};
+
+use anyhow::Context as _;
+use influencer::{
+    client::Client,
+    message::{self as m, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _},
+};
+use serde::{Deserialize, de::Error as _};
+use serde_json::{Value, json};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tungstenite::Message as WsMessage;
+use wildmatch::WildMatch;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Action {
+    request: String,
+    data: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    name: Option<String>,
+    #[serde(deserialize_with = "pattern")]
+    when: WildMatch,
+    #[serde(default, rename = "where", deserialize_with = "predicates")]
+    conditions: Vec<Predicate>,
+    /// Variable names to JSON pointers into `eventData`.
+    #[serde(default, deserialize_with = "captures")]
+    capture: BTreeMap<String, String>,
+    then: Vec<Action>,
+    halt_on_failure: Option<bool>,
+}
+impl Rule {
+    /// For messages: the name if it has one, or its position in the file.
+    fn name(&self, index: usize) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("#{}", index + 1),
+        }
+    }
+    fn filter(&self) -> EventFilter {
+        EventFilter {
+            types: vec![self.when.clone()],
+            predicates: self.conditions.clone(),
+            ..Default::default()
+        }
+    }
+    /// The request or batch for `event`, under `request_id`.
+    fn message(&self, event: &m::AnyEvent, request_id: &str) -> anyhow::Result<WsMessage> {
+        let mut variables = HashMap::new();
+        if let Some(Value::Object(ref fields)) = event.event_data {
+            for (name, value) in fields {
+                variables.insert(name.clone(), value.clone());
+            }
+        }
+        for (name, pointer) in &self.capture {
+            let value = event
+                .event_data
+                .as_ref()
+                .and_then(|data| data.pointer(pointer))
+                .unwrap_or(&Value::Null);
+            variables.insert(name.clone(), value.clone());
+        }
+        let mut requests = Vec::new();
+        for action in &self.then {
+            let data = match action.data {
+                Some(ref data) => Some(fill_placeholders(data, &variables)?),
+                None => None,
+            };
+            requests.push((&action.request, data));
+        }
+        match &requests[..] {
+            [(request_type, data)] => Ok(m::Request {
+                request_type,
+                request_id,
+                request_data: data.as_ref(),
+            }
+            .into_ws_message_json()?),
+            _ => Ok(m::RequestBatch {
+                request_id,
+                halt_on_failure: self.halt_on_failure,
+                execution_type: None,
+                requests: requests
+                    .iter()
+                    .map(|(request_type, data)| match data {
+                        Some(data) => json!({"requestType": request_type, "requestData": data}),
+                        None => json!({"requestType": request_type}),
+                    })
+                    .collect::<Vec<_>>(),
+            }
+            .into_ws_message_json()?),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rules {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+impl Rules {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read rules {}", path.display()))?;
+        let rules: Self = config::parse_file(path, &text)
+            .with_context(|| format!("Invalid rules {}", path.display()))?;
+        if let Some(i) = rules.rules.iter().position(|rule| rule.then.is_empty()) {
+            anyhow::bail!("Rule {} has nothing to do", rules.rules[i].name(i));
+        }
+        Ok(rules)
+    }
+    /// The `when` patterns of all rules.
+    pub fn event_types(&self) -> Vec<WildMatch> {
+        self.rules.iter().map(|rule| rule.when.clone()).collect()
+    }
+}
+
+/// Evaluates `rules` against events until `interrupted` is set. With
+/// `dry_run`, the requests are printed instead of sent.
+pub fn run(
+    client: &mut Client,
+    rules: &Rules,
+    dry_run: bool,
+    output: &Output,
+    interrupted: &AtomicBool,
+) -> anyhow::Result<()> {
+    let filters: Vec<EventFilter> = rules.rules.iter().map(Rule::filter).collect();
+    // Request ids to the names of the rules that sent them
+    let mut sent = HashMap::new();
+    let mut next_id = 0u64;
+    let mut reconnects = client.reconnects();
+    while !interrupted.load(Ordering::Relaxed) {
+        let message = client.try_read()?;
+        if client.reconnects() != reconnects {
+            reconnects = client.reconnects();
+            // Their responses will never come
+            for (_, rule) in sent.drain() {
+                eprintln!("Rule {rule}: {}", session::LOST);
+            }
+        }
+        let Some(message) = message else {
+            continue;
+        };
+        match message.any_obs_server_message()? {
+            ServerMessage::Event(_) => {
+                let event = message.obs_message_data::<m::AnyEvent>()?;
+                for (i, (rule, filter)) in rules.rules.iter().zip(&filters).enumerate() {
+                    if !filter.matches(&event) {
+                        continue;
+                    }
+                    let request_id = format!("rule-{next_id}");
+                    next_id += 1;
+                    let request = match rule.message(&event, &request_id) {
+                        Ok(request) => request,
+                        Err(error) => {
+                            eprintln!("Rule {}: {error:#}", rule.name(i));
+                            continue;
+                        }
+                    };
+                    if dry_run {
+                        let request: Value = serde_json::from_str(request.to_text()?)?;
+                        let action = json!({"rule": rule.name(i), "request": request["d"]});
+                        output.print(&action)?;
+                    } else {
+                        client.send(request)?;
+                        sent.insert(request_id, rule.name(i));
+                    }
+                }
+            }
+            ServerMessage::Response(_) => {
+                let response = message.obs_message_data::<m::AnyResponse>()?;
+                let Some(rule) = sent.remove(response.request_id) else {
+                    continue;
+                };
+                let status = response.request_status;
+                if !status.result {
+                    eprintln!(
+                        "Rule {rule}: {} failed with code {}: {}",
+                        response.request_type,
+                        status.code,
+                        status.comment.unwrap_or("no comment")
+                    );
+                }
+            }
+            ServerMessage::ResponseBatch(_) => {
+                let response = message.obs_message_data::<m::AnyResponseBatch>()?;
+                let Some(rule) = sent.remove(response.request_id) else {
+                    continue;
+                };
+                for result in &response.results {
+                    let status = &result.request_status;
+                    if !status.result {
+                        eprintln!(
+                            "Rule {rule}: {} failed with code {}: {}",
+                            result.request_type,
+                            status.code,
+                            status.comment.unwrap_or("no comment")
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn pattern<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<WildMatch, D::Error> {
+    Ok(WildMatch::new(&String::deserialize(deserializer)?))
+}
+
+fn predicates<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Predicate>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|text| Predicate::parse(text).map_err(D::Error::custom))
+        .collect()
+}
+
+fn captures<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, String>, D::Error> {
+    BTreeMap::<String, String>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, path)| Ok((name, path_to_pointer(&path).map_err(D::Error::custom)?)))
+        .collect()
+}
@@ -0,0 +1,351 @@
+//! `influencer script`: runs a Rhai script against one connection to OBS.
+//!
+//! Scripts get these functions besides Rhai's own:
+//!
+//! - `request(type)`, `request(type, data)`: sends a request, and returns
+//!   its `responseData`, or `()` if there is none. Failed requests throw.
+//!   (Rhai keeps the name `call` for calling function pointers.)
+//! - `batch(requests)`, `batch(requests, halt_on_failure)`: sends an array
+//!   of `#{requestType, requestData}` maps as a batch, and returns the array
+//!   of results.
+//! - `on(event_type, fn)`: calls `fn(event)` for each event whose type
+//!   matches the glob `event_type`. `event` is `#{eventType, eventIntent,
+//!   eventData}`.
+//! - `after(ms, fn)`, `every(ms, fn)`: calls `fn()` once after, or
+//!   repeatedly every, `ms` milliseconds. Both return an id for `cancel(id)`.
+//! - `sleep(ms)`
+//!
+//! The script's command-line arguments are in `ARGS`. Once the script has
+//! run, events and timers are handled until `exit()` is called, or until
+//! there are neither handlers nor timers left. `exit(code)` with an integer
+//! exits with that code. Ctrl-C stops the script wherever it is, even while
+//! it waits for a response or sleeps.
+
+use crate::protocol;
+
+use anyhow::Context as _;
+use influencer::{
+    client::{self, Client},
+    message::{self as m, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _},
+};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, FnPtr, Scope};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    path::Path,
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tungstenite::Message as WsMessage;
+use wildmatch::WildMatch;
+
+/// How long to wait for OBS before checking timers.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+struct Timer {
+    id: i64,
+    due: Instant,
+    interval: Option<Duration>,
+    callback: FnPtr,
+}
+
+struct State {
+    client: Client,
+    next_id: u64,
+    /// Events received while waiting for a response, to be handled later.
+    events: VecDeque<WsMessage>,
+    handlers: Vec<(WildMatch, FnPtr)>,
+    subscriptions: u32,
+    timers: Vec<Timer>,
+    next_timer: i64,
+    interrupted: Arc<AtomicBool>,
+}
+impl State {
+    /// Sends a request or batch and waits for its response.
+    fn call(
+        &mut self,
+        message: impl FnOnce(&str) -> anyhow::Result<WsMessage>,
+    ) -> anyhow::Result<WsMessage> {
+        let request_id = format!("script-{}", self.next_id);
+        self.next_id += 1;
+        self.client.send(message(&request_id)?)?;
+        loop {
+            if self.interrupted.load(Ordering::Relaxed) {
+                anyhow::bail!("Interrupted");
+            }
+            let Some(message) = self.client.try_read()? else {
+                continue;
+            };
+            let id = match message.any_obs_server_message()? {
+                ServerMessage::Event(_) => {
+                    self.events.push_back(message);
+                    continue;
+                }
+                ServerMessage::Response(info) => info.request_id,
+                ServerMessage::ResponseBatch(info) => info.request_id,
+                _ => continue,
+            };
+            if id == request_id {
+                return Ok(message);
+            }
+        }
+    }
+    fn request(&mut self, request_type: &str, data: Option<Value>) -> anyhow::Result<Dynamic> {
+        let response = self.call(|request_id| {
+            Ok(m::Request {
+                request_type,
+                request_id,
+                request_data: data,
+            }
+            .into_ws_message_json()?)
+        })?;
+        let response = response.obs_message_data::<m::AnyResponse>()?;
+        let status = response.request_status;
+        if !status.result {
+            anyhow::bail!(
+                "{request_type} failed with code {}: {}",
+                status.code,
+                status.comment.unwrap_or("no comment")
+            );
+        }
+        match response.response_data {
+            Some(data) => to_dynamic(data),
+            None => Ok(Dynamic::UNIT),
+        }
+    }
+    fn batch(&mut self, requests: Value, halt_on_failure: Option<bool>) -> anyhow::Result<Dynamic> {
+        let response = self.call(|request_id| {
+            Ok(m::RequestBatch {
+                request_id,
+                halt_on_failure,
+                execution_type: None,
+                requests,
+            }
+            .into_ws_message_json()?)
+        })?;
+        let response = response.obs_message_data::<m::AnyResponseBatch>()?;
+        to_dynamic(response.results)
+    }
+    fn on(&mut self, event_type: &str, callback: FnPtr) -> anyhow::Result<()> {
+        let pattern = WildMatch::new(event_type);
+        // Unknown event types may still exist, so subscribe to all but the
+        // high-volume ones
+        let subscriptions = protocol::event_subscriptions(std::slice::from_ref(&pattern))
+            .unwrap_or(m::event_subscription::ALL);
+        self.handlers.push((pattern, callback));
+        if self.subscriptions | subscriptions != self.subscriptions {
+            self.subscriptions |= subscriptions;
+            self.client.reidentify(Some(self.subscriptions))?;
+        }
+        Ok(())
+    }
+    fn timer(&mut self, ms: i64, repeat: bool, callback: FnPtr) -> i64 {
+        let interval = Duration::from_millis(ms.max(0) as u64);
+        self.next_timer += 1;
+        self.timers.push(Timer {
+            id: self.next_timer,
+            due: Instant::now() + interval,
+            interval: repeat.then_some(interval),
+            callback,
+        });
+        self.next_timer
+    }
+}
+
+fn script_error(error: impl std::fmt::Display) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+/// Like `rhai::serde::to_dynamic`, whose errors can't cross threads.
+fn to_dynamic(value: impl serde::Serialize) -> anyhow::Result<Dynamic> {
+    rhai::serde::to_dynamic(value).map_err(|error| anyhow::anyhow!("{error}"))
+}
+
+fn to_value(value: &Dynamic) -> RhaiResult<Value> {
+    rhai::serde::from_dynamic(value)
+}
+
+fn register(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+    let s = state.clone();
+    engine.register_fn(
+        "request",
+        move |request_type: &str| -> RhaiResult<Dynamic> {
+            let result = s.borrow_mut().request(request_type, None);
+            result.map_err(|error| script_error(format!("{error:#}")))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "request",
+        move |request_type: &str, data: Dynamic| -> RhaiResult<Dynamic> {
+            let data = to_value(&data)?;
+            let result = s.borrow_mut().request(request_type, Some(data));
+            result.map_err(|error| script_error(format!("{error:#}")))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("batch", move |requests: Array| -> RhaiResult<Dynamic> {
+        let requests = to_value(&requests.into())?;
+        let result = s.borrow_mut().batch(requests, None);
+        result.map_err(|error| script_error(format!("{error:#}")))
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "batch",
+        move |requests: Array, halt_on_failure: bool| -> RhaiResult<Dynamic> {
+            let requests = to_value(&requests.into())?;
+            let result = s.borrow_mut().batch(requests, Some(halt_on_failure));
+            result.map_err(|error| script_error(format!("{error:#}")))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn(
+        "on",
+        move |event_type: &str, callback: FnPtr| -> RhaiResult<()> {
+            let result = s.borrow_mut().on(event_type, callback);
+            result.map_err(|error| script_error(format!("{error:#}")))
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("after", move |ms: i64, callback: FnPtr| {
+        s.borrow_mut().timer(ms, false, callback)
+    });
+    let s = state.clone();
+    engine.register_fn("every", move |ms: i64, callback: FnPtr| {
+        s.borrow_mut().timer(ms, true, callback)
+    });
+    let s = state.clone();
+    engine.register_fn("cancel", move |id: i64| {
+        s.borrow_mut().timers.retain(|timer| timer.id != id);
+    });
+    let interrupted = state.borrow().interrupted.clone();
+    engine.register_fn("sleep", move |ms: i64| {
+        let end = Instant::now() + Duration::from_millis(ms.max(0) as u64);
+        // In slices, so that Ctrl-C doesn't wait for the rest of it
+        loop {
+            let left = end.saturating_duration_since(Instant::now());
+            if left.is_zero() || interrupted.load(Ordering::Relaxed) {
+                break;
+            }
+            std::thread::sleep(left.min(POLL_INTERVAL));
+        }
+    });
+    // Stops the script at its next operation, which can't be caught
+    let interrupted = state.borrow().interrupted.clone();
+    engine.on_progress(move |_| interrupted.load(Ordering::Relaxed).then_some(Dynamic::UNIT));
+}
+
+/// Runs the script at `path`. Returns the code passed to `exit`, if any.
+pub fn run(
+    mut config: client::Config,
+    path: &Path,
+    args: Vec<String>,
+    interrupted: &Arc<AtomicBool>,
+) -> anyhow::Result<Option<i32>> {
+    let mut engine = Engine::new();
+    let ast = engine
+        .compile_file(path.into())
+        .map_err(|error| anyhow::anyhow!("{error}"))
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    config.read_timeout = Some(POLL_INTERVAL);
+    config.event_subscriptions = Some(m::event_subscription::NONE);
+    let state = Rc::new(RefCell::new(State {
+        client: Client::connect(config)?,
+        next_id: 0,
+        events: VecDeque::new(),
+        handlers: Vec::new(),
+        subscriptions: m::event_subscription::NONE,
+        timers: Vec::new(),
+        next_timer: 0,
+        interrupted: interrupted.clone(),
+    }));
+    register(&mut engine, &state);
+    let mut scope = Scope::new();
+    let args: Array = args.into_iter().map(Dynamic::from).collect();
+    scope.push_constant("ARGS", args);
+    let result = engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .and_then(|()| event_loop(&engine, &ast, &state, interrupted));
+    let code = match result {
+        Ok(()) => None,
+        Err(error) => match *error {
+            EvalAltResult::Exit(value, _) => value.as_int().ok().map(|code| code as i32),
+            EvalAltResult::ErrorTerminated(..) => None,
+            error => return Err(anyhow::anyhow!("{error}")),
+        },
+    };
+    crate::close(&mut state.borrow_mut().client);
+    Ok(code)
+}
+
+fn event_loop(
+    engine: &Engine,
+    ast: &rhai::AST,
+    state: &Rc<RefCell<State>>,
+    interrupted: &AtomicBool,
+) -> RhaiResult<()> {
+    loop {
+        if interrupted.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let due = {
+            let mut state = state.borrow_mut();
+            if state.handlers.is_empty() && state.timers.is_empty() {
+                return Ok(());
+            }
+            let now = Instant::now();
+            let mut due = Vec::new();
+            state.timers.retain_mut(|timer| {
+                if timer.due > now {
+                    return true;
+                }
+                due.push(timer.callback.clone());
+                match timer.interval {
+                    Some(interval) => {
+                        timer.due += interval;
+                        true
+                    }
+                    None => false,
+                }
+            });
+            due
+        };
+        for callback in due {
+            let _: Dynamic = callback.call(engine, ast, ())?;
+        }
+        let message = {
+            let mut state = state.borrow_mut();
+            match state.events.pop_front() {
+                Some(message) => Some(message),
+                None => state.client.try_read().map_err(script_error)?,
+            }
+        };
+        let Some(message) = message else {
+            continue;
+        };
+        let Ok(event) = message.obs_message_data::<m::AnyEvent>() else {
+            continue;
+        };
+        let handlers: Vec<FnPtr> = state
+            .borrow()
+            .handlers
+            .iter()
+            .filter(|(pattern, _)| pattern.matches(event.event_type))
+            .map(|(_, callback)| callback.clone())
+            .collect();
+        if handlers.is_empty() {
+            continue;
+        }
+        let event = rhai::serde::to_dynamic(&event)?;
+        for callback in handlers {
+            let _: Dynamic = callback.call(engine, ast, (event.clone(),))?;
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! One connection to OBS, shared by the threads of a long-running command.
+//!
+//! A background thread owns the [`Client`]. Requests and batches are sent
+//! under request ids of its own choosing, so callers don't need to worry
+//! about collisions; responses are handed back with the caller's request id
+//! restored. Requests whose responses were lost to a reconnect are answered
+//! with a failed `requestStatus` instead.
+
+use anyhow::Context as _;
+use influencer::{
+    client::{self, Client},
+    message::{
+        self as m, ClientMessage, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _,
+        status_code,
+    },
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use tungstenite::{
+    Message as WsMessage,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+/// How long the session thread waits for OBS before checking for requests.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long [`Session::call`] waits for a response.
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// The `requestStatus` comment for requests pending when the connection was
+/// lost.
+pub const LOST: &str = "Connection to OBS lost, the request may or may not have been executed.";
+
+enum Command {
+    Send {
+        message: WsMessage,
+        request_id: String,
+        reply: Sender<WsMessage>,
+    },
+    Close,
+}
+
+/// Where to send the response to a request sent to OBS.
+struct Route {
+    request_id: String,
+    /// The caller's request or batch, to answer it if OBS never will.
+    message: WsMessage,
+    reply: Sender<WsMessage>,
+}
+
+pub struct Session {
+    commands: Sender<Command>,
+    thread: JoinHandle<anyhow::Result<()>>,
+}
+impl Session {
+    /// Connects to OBS. If `events` is given, events are sent there.
+    pub fn connect(
+        mut config: client::Config,
+        events: Option<Sender<WsMessage>>,
+    ) -> anyhow::Result<Self> {
+        config.read_timeout = Some(POLL_INTERVAL);
+        let client = Client::connect(config)?;
+        let (commands, incoming) = mpsc::channel();
+        let thread = thread::spawn(move || run(client, incoming, events));
+        Ok(Self { commands, thread })
+    }
+    /// Sends a request or batch message. Its response is sent to `reply`.
+    pub fn send(&self, message: WsMessage, reply: Sender<WsMessage>) -> anyhow::Result<()> {
+        let request_id = match message.any_obs_client_message()? {
+            ClientMessage::Request(info) => info.request_id.to_owned(),
+            ClientMessage::RequestBatch(info) => info.request_id.to_owned(),
+            _ => anyhow::bail!("Not a request or request batch"),
+        };
+        let command = Command::Send {
+            message,
+            request_id,
+            reply,
+        };
+        self.commands
+            .send(command)
+            .ok()
+            .context("Connection to OBS lost")
+    }
+    /// Sends a request or batch message, and waits for its response.
+    pub fn call(&self, message: WsMessage) -> anyhow::Result<WsMessage> {
+        let (reply, response) = mpsc::channel();
+        self.send(message, reply)?;
+        match response.recv_timeout(RESPONSE_TIMEOUT) {
+            Ok(response) => Ok(response),
+            Err(RecvTimeoutError::Timeout) => anyhow::bail!("Timed out waiting for OBS to respond"),
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Connection to OBS lost"),
+        }
+    }
+    /// Starts closing the connection, for when the session is shared and
+    /// can't be [closed](Session::close) by value. See
+    /// [`Session::is_finished`].
+    pub fn start_close(&self) {
+        let _ = self.commands.send(Command::Close);
+    }
+    /// Whether the connection has been lost for good.
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+    /// Closes the connection, or returns the error that ended it.
+    pub fn close(self) -> anyhow::Result<()> {
+        let _ = self.commands.send(Command::Close);
+        match self.thread.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+fn run(
+    mut client: Client,
+    commands: Receiver<Command>,
+    events: Option<Sender<WsMessage>>,
+) -> anyhow::Result<()> {
+    // Keyed by the request id sent to OBS
+    let mut routes: HashMap<String, Route> = HashMap::new();
+    let mut next_id = 0u64;
+    let mut connection = 0;
+    // Responses to requests sent before a reconnect will never arrive
+    let mut fail_lost = |routes: &mut HashMap<String, Route>, client: &Client| {
+        if client.reconnects() == connection {
+            return;
+        }
+        connection = client.reconnects();
+        for (_, route) in routes.drain() {
+            match failed_response(&route.message, LOST) {
+                Ok(response) => {
+                    let _ = route.reply.send(response);
+                }
+                Err(error) => eprintln!("Failed to answer a lost request: {error}"),
+            }
+        }
+    };
+    loop {
+        for command in commands.try_iter() {
+            match command {
+                Command::Send {
+                    message,
+                    request_id,
+                    reply,
+                } => {
+                    let session_id = next_id.to_string();
+                    next_id += 1;
+                    client.send(with_request_id(&message, &session_id)?)?;
+                    // The message itself was resent after any reconnect
+                    fail_lost(&mut routes, &client);
+                    let route = Route {
+                        request_id,
+                        message,
+                        reply,
+                    };
+                    routes.insert(session_id, route);
+                }
+                Command::Close => {
+                    let frame = CloseFrame {
+                        code: CloseCode::Normal,
+                        reason: "".into(),
+                    };
+                    return Ok(client.close(Some(frame))?);
+                }
+            }
+        }
+        let message = client.try_read()?;
+        fail_lost(&mut routes, &client);
+        let Some(message) = message else {
+            continue;
+        };
+        let session_id = match message.any_obs_server_message() {
+            Ok(ServerMessage::Response(info)) => info.request_id.to_owned(),
+            Ok(ServerMessage::ResponseBatch(info)) => info.request_id.to_owned(),
+            Ok(ServerMessage::Event(_)) => {
+                if let Some(ref events) = events {
+                    let _ = events.send(message);
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if let Some(route) = routes.remove(&session_id) {
+            let _ = route
+                .reply
+                .send(with_request_id(&message, &route.request_id)?);
+        }
+    }
+}
+
+/// Copies an OBS message with `d.requestId` replaced.
+pub fn with_request_id(message: &WsMessage, request_id: &str) -> anyhow::Result<WsMessage> {
+    let mut value: Value = serde_json::from_str(message.to_text()?)?;
+    value["d"]["requestId"] = request_id.into();
+    Ok(WsMessage::text(value.to_string()))
+}
+
+/// A failed response to a request or batch message, with `comment` as the
+/// `requestStatus` comment.
+pub fn failed_response(message: &WsMessage, comment: &str) -> anyhow::Result<WsMessage> {
+    match message.any_obs_client_message()? {
+        ClientMessage::RequestBatch(info) => {
+            let requests = message
+                .obs_message_data::<m::RequestBatchVec<Value>>()?
+                .requests;
+            failed_batch(&info, &requests, &vec![comment; requests.len()])
+        }
+        ClientMessage::Request(info) => failed(&info, comment),
+        _ => anyhow::bail!("Not a request or request batch"),
+    }
+}
+
+pub fn failed(request: &m::request::InfoPart, comment: &str) -> anyhow::Result<WsMessage> {
+    let response = m::Response::<()> {
+        request_type: request.request_type,
+        request_id: request.request_id,
+        request_status: m::response::RequestStatus {
+            result: false,
+            code: status_code::GENERIC_ERROR,
+            comment: Some(comment),
+        },
+        response_data: None,
+    };
+    Ok(response.into_ws_message_json()?)
+}
+
+/// `comments` has one entry per request.
+pub fn failed_batch(
+    batch: &m::request_batch::InfoPart,
+    requests: &[m::request_batch::RequestsItem<Value>],
+    comments: &[&str],
+) -> anyhow::Result<WsMessage> {
+    let results = requests
+        .iter()
+        .zip(comments)
+        .map(|(request, comment)| m::response_batch::ResultsItem::<()> {
+            request_type: request.request_type,
+            request_id: request.request_id,
+            request_status: m::response::RequestStatus {
+                result: false,
+                code: status_code::GENERIC_ERROR,
+                comment: Some(comment),
+            },
+            response_data: None,
+        })
+        .collect::<Vec<_>>();
+    let response = m::ResponseBatch {
+        request_id: batch.request_id,
+        results,
+    };
+    Ok(response.into_ws_message_json()?)
+}
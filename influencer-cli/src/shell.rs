@@ -0,0 +1,134 @@
+//! `influencer shell`: an interactive prompt sending requests over one
+//! connection. Lines are a request type, optionally followed by JSON data.
+
+use crate::{json_serialize, protocol::PROTOCOL, session::Session};
+
+use anyhow::Context as _;
+use influencer::{
+    client,
+    message::{self as m, IntoWsMessageJson as _, WsMessageExt as _},
+};
+use rustyline::{
+    Context, ExternalPrinter as _, Helper, completion::Completer, error::ReadlineError,
+    highlight::Highlighter, hint::Hinter, validate::Validator,
+};
+use serde_json::Value;
+use std::{path::PathBuf, sync::mpsc, thread};
+
+const PROMPT: &str = "obs> ";
+
+/// Completes request type names at the start of the line.
+struct RequestTypeHelper;
+impl Completer for RequestTypeHelper {
+    type Candidate = String;
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = PROTOCOL
+            .requests
+            .iter()
+            .map(|request| &request.request_type)
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((0, candidates))
+    }
+}
+impl Hinter for RequestTypeHelper {
+    type Hint = String;
+}
+impl Highlighter for RequestTypeHelper {}
+impl Validator for RequestTypeHelper {}
+impl Helper for RequestTypeHelper {}
+
+/// Runs the prompt until end of input. If `events` is set, events are
+/// printed above the prompt as they arrive.
+pub fn run(config: client::Config, events: bool, pretty: bool) -> anyhow::Result<()> {
+    let mut editor = rustyline::Editor::new()?;
+    editor.set_helper(Some(RequestTypeHelper));
+    let history = history_path();
+    if let Some(ref history) = history {
+        let _ = editor.load_history(history);
+    }
+    let (event_sender, event_receiver) = mpsc::channel();
+    let session = Session::connect(config, events.then_some(event_sender))?;
+    let mut print: Box<dyn FnMut(String) -> bool + Send> = match editor.create_external_printer() {
+        Ok(mut printer) => Box::new(move |text| printer.print(text).is_ok()),
+        // Not a terminal, so there's no prompt to print around
+        Err(_) => Box::new(|text| {
+            print!("{text}");
+            true
+        }),
+    };
+    thread::spawn(move || {
+        for event in event_receiver {
+            let Ok(event) = event.obs_message_data::<m::AnyEvent>() else {
+                continue;
+            };
+            let mut text = Vec::new();
+            if json_serialize(pretty, &event, &mut text).is_err() {
+                continue;
+            }
+            text.push(b'\n');
+            if !print(String::from_utf8_lossy(&text).into_owned()) {
+                break;
+            }
+        }
+    });
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+        if matches!(line, "exit" | "quit") {
+            break;
+        }
+        if let Err(error) = request(&session, line, pretty) {
+            eprintln!("{error:#}");
+        }
+        if session.is_finished() {
+            break;
+        }
+    }
+    if let Some(ref history) = history {
+        let _ = editor.save_history(history);
+    }
+    session.close()
+}
+
+fn request(session: &Session, line: &str, pretty: bool) -> anyhow::Result<()> {
+    let (request_type, data) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let data: Option<Value> = match data.trim() {
+        "" => None,
+        data => Some(serde_json::from_str(data).context("Invalid request data")?),
+    };
+    let request = m::Request {
+        request_type,
+        request_id: "shell",
+        request_data: data,
+    };
+    let response = session.call(request.into_ws_message_json()?)?;
+    let response = response.obs_message_data::<m::AnyResponse>()?;
+    crate::json_print(pretty, &response)?;
+    Ok(())
+}
+
+fn history_path() -> Option<PathBuf> {
+    let dir = dirs::data_local_dir()?.join("influencer");
+    std::fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("shell_history"))
+}
@@ -0,0 +1,376 @@
+//! `influencer run-file`: runs a file of requests, sleeps and waits in
+//! order, feeding values from earlier responses into later requests.
+//!
+//! ```toml
+//! [[step]]
+//! request = "GetSceneItemId"
+//! data = { sceneName = "Game", sourceName = "Webcam" }
+//! capture = { id = ".sceneItemId" }
+//!
+//! [[step]]
+//! request = "SetSceneItemEnabled"
+//! data = { sceneName = "Game", sceneItemId = "{id}", sceneItemEnabled = false }
+//!
+//! [[step]]
+//! wait = "SceneItemEnableStateChanged"
+//! where = [".sceneItemId == {id}"]
+//! timeout = 5
+//!
+//! [[step]]
+//! sleep = 0.5
+//!
+//! [[step]]
+//! request = "GetSceneItemEnabled"
+//! data = { sceneName = "Game", sceneItemId = "{id}" }
+//! assert = [".sceneItemEnabled == false"]
+//! ```
+//!
+//! Each step either sends a `request`, `sleep`s for some seconds, or
+//! `wait`s for an event whose type matches a glob and whose data matches
+//! the `where` predicates, for at most `timeout` seconds if given. A wait
+//! also sees events that happened during the step before it.
+//!
+//! `capture` takes values from `responseData`, or from `eventData` for
+//! waits, and `assert` checks them with predicates like `where`. Request
+//! data, `where` and `assert` may contain `{name}` placeholders like macros
+//! do, filled from captures and the `NAME=VALUE` arguments. A captured
+//! value filling a whole string keeps its type, so `"{id}"` above is a
+//! number; arguments are strings unless used as `{name:json}`. The run
+//! stops at the first failed request, assertion or wait.
+//!
+//! Each response and awaited event is printed. A request that OBS doesn't
+//! answer in time fails like any other.
+//!
+//! Steps files are TOML, or YAML for `.yaml` and `.yml` files, with a list
+//! of the same entries under `step:`.
+
+use crate::{
+    config::{self, fill_placeholders},
+    filter::{EventFilter, Predicate, path_to_pointer},
+    output::Output,
+    session,
+};
+
+use anyhow::Context as _;
+use influencer::{
+    client::Client,
+    message::{self as m, IntoWsMessageJson as _, ServerMessage, WsMessageExt as _},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    path::Path,
+    time::{Duration, Instant},
+};
+use tungstenite::Message as WsMessage;
+use wildmatch::WildMatch;
+
+#[derive(Debug)]
+enum Action {
+    Request {
+        request_type: String,
+        data: Option<Value>,
+    },
+    Sleep(Duration),
+    Wait {
+        event_type: WildMatch,
+        conditions: Vec<String>,
+        timeout: Option<Duration>,
+    },
+}
+
+/// A step as written in the file, before checking that it does exactly
+/// one thing.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStep {
+    name: Option<String>,
+    request: Option<String>,
+    data: Option<Value>,
+    sleep: Option<f64>,
+    wait: Option<String>,
+    #[serde(default, rename = "where")]
+    conditions: Vec<String>,
+    timeout: Option<f64>,
+    #[serde(default)]
+    capture: BTreeMap<String, String>,
+    #[serde(default, rename = "assert")]
+    assertions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "RawStep")]
+pub struct Step {
+    name: Option<String>,
+    action: Action,
+    /// Variable names to JSON pointers into the response or event data.
+    capture: BTreeMap<String, String>,
+    assertions: Vec<String>,
+}
+impl Step {
+    /// For messages: the name if it has one, or its position in the file.
+    fn name(&self, index: usize) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("#{}", index + 1),
+        }
+    }
+}
+impl TryFrom<RawStep> for Step {
+    type Error = String;
+    fn try_from(raw: RawStep) -> Result<Self, String> {
+        let fields = [
+            ("data", raw.data.is_some()),
+            ("where", !raw.conditions.is_empty()),
+            ("timeout", raw.timeout.is_some()),
+            ("capture", !raw.capture.is_empty()),
+            ("assert", !raw.assertions.is_empty()),
+        ];
+        let action = match (raw.request, raw.sleep, raw.wait) {
+            (Some(request_type), None, None) => Action::Request {
+                request_type,
+                data: raw.data,
+            },
+            (None, Some(seconds), None) => Action::Sleep(seconds_to_duration(seconds)?),
+            (None, None, Some(event_type)) => Action::Wait {
+                event_type: WildMatch::new(&event_type),
+                conditions: raw.conditions,
+                timeout: raw.timeout.map(seconds_to_duration).transpose()?,
+            },
+            _ => return Err("expected exactly one of request, sleep and wait".to_owned()),
+        };
+        let allowed: &[&str] = match action {
+            Action::Request { .. } => &["data", "capture", "assert"],
+            Action::Sleep(_) => &[],
+            Action::Wait { .. } => &["where", "timeout", "capture", "assert"],
+        };
+        if let Some((field, _)) = fields
+            .iter()
+            .find(|(field, present)| *present && !allowed.contains(field))
+        {
+            return Err(format!("{field} can't be used in this step"));
+        }
+        if let Action::Wait { ref conditions, .. } = action {
+            for text in conditions {
+                Predicate::parse(text)?;
+            }
+        }
+        for text in &raw.assertions {
+            Predicate::parse(text)?;
+        }
+        let capture = raw
+            .capture
+            .into_iter()
+            .map(|(name, path)| Ok((name, path_to_pointer(&path)?)))
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            name: raw.name,
+            action,
+            capture,
+            assertions: raw.assertions,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Steps {
+    #[serde(default, rename = "step")]
+    steps: Vec<Step>,
+}
+impl Steps {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read steps {}", path.display()))?;
+        config::parse_file(path, &text).with_context(|| format!("Invalid steps {}", path.display()))
+    }
+    /// The event types waited for.
+    pub fn event_types(&self) -> Vec<WildMatch> {
+        self.steps
+            .iter()
+            .filter_map(|step| match step.action {
+                Action::Wait { ref event_type, .. } => Some(event_type.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Runs `steps` in order, with `params` as the initial variables.
+pub fn run(
+    client: &mut Client,
+    steps: &Steps,
+    params: &[(String, String)],
+    output: &Output,
+) -> anyhow::Result<()> {
+    let mut variables: HashMap<String, Value> = params
+        .iter()
+        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+        .collect();
+    // Events read during the previous step
+    let mut recent = VecDeque::new();
+    for (i, step) in steps.steps.iter().enumerate() {
+        let mut earlier = std::mem::take(&mut recent);
+        let data = run_step(client, step, &variables, &mut earlier, &mut recent, output)
+            .with_context(|| format!("Step {}", step.name(i)))?;
+        for (name, pointer) in &step.capture {
+            let value = data
+                .as_ref()
+                .and_then(|data| data.pointer(pointer))
+                .unwrap_or(&Value::Null);
+            variables.insert(name.clone(), value.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Runs one step, checks its assertions, and returns the data to capture
+/// from. Events read go to `recent`, except for the one a wait is for, and
+/// waits look at the `earlier` ones first.
+fn run_step(
+    client: &mut Client,
+    step: &Step,
+    variables: &HashMap<String, Value>,
+    earlier: &mut VecDeque<WsMessage>,
+    recent: &mut VecDeque<WsMessage>,
+    output: &Output,
+) -> anyhow::Result<Option<Value>> {
+    let data = match step.action {
+        Action::Request {
+            ref request_type,
+            ref data,
+        } => {
+            let data = match data {
+                Some(data) => Some(fill_placeholders(data, variables)?),
+                None => None,
+            };
+            let response = request(client, request_type, data.as_ref(), recent)?;
+            let response = response.obs_message_data::<m::AnyResponse>()?;
+            output.print(&response)?;
+            let status = &response.request_status;
+            if !status.result {
+                anyhow::bail!(
+                    "{request_type} failed with code {}: {}",
+                    status.code,
+                    status.comment.unwrap_or("no comment")
+                );
+            }
+            response.response_data
+        }
+        Action::Sleep(duration) => {
+            std::thread::sleep(duration);
+            None
+        }
+        Action::Wait {
+            ref event_type,
+            ref conditions,
+            timeout,
+        } => {
+            let filter = EventFilter {
+                types: vec![event_type.clone()],
+                predicates: predicates(conditions, variables)?,
+                ..Default::default()
+            };
+            let Some(event) = wait(client, &filter, timeout, earlier, recent)? else {
+                anyhow::bail!("Timed out waiting for {event_type}");
+            };
+            let event = event.obs_message_data::<m::AnyEvent>()?;
+            output.print(&event)?;
+            event.event_data
+        }
+    };
+    for text in &step.assertions {
+        let text = fill_text(text, variables)?;
+        let assertion = Predicate::parse(&text).map_err(anyhow::Error::msg)?;
+        if !assertion.matches(data.as_ref()) {
+            anyhow::bail!("Assertion failed: {text}");
+        }
+    }
+    Ok(data)
+}
+
+/// Sends a request and reads until its response, keeping events for later.
+/// Gives up after as long as the other commands sharing a connection do.
+fn request(
+    client: &mut Client,
+    request_type: &str,
+    data: Option<&Value>,
+    events: &mut VecDeque<WsMessage>,
+) -> anyhow::Result<WsMessage> {
+    let request_id = "run-file";
+    client.send(
+        m::Request {
+            request_type,
+            request_id,
+            request_data: data,
+        }
+        .into_ws_message_json()?,
+    )?;
+    let deadline = Instant::now() + session::RESPONSE_TIMEOUT;
+    while Instant::now() < deadline {
+        let Some(message) = client.try_read()? else {
+            continue;
+        };
+        match message.any_obs_server_message()? {
+            ServerMessage::Event(_) => events.push_back(message),
+            ServerMessage::Response(info) if info.request_id == request_id => return Ok(message),
+            _ => {}
+        }
+    }
+    anyhow::bail!("Timed out waiting for OBS to respond to {request_type}")
+}
+
+/// Returns the first event matching `filter`, looking through `earlier`
+/// events before reading new ones, or `None` on timeout.
+fn wait(
+    client: &mut Client,
+    filter: &EventFilter,
+    timeout: Option<Duration>,
+    earlier: &mut VecDeque<WsMessage>,
+    recent: &mut VecDeque<WsMessage>,
+) -> anyhow::Result<Option<WsMessage>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    while let Some(message) = earlier.pop_front() {
+        if filter.matches(&message.obs_message_data::<m::AnyEvent>()?) {
+            return Ok(Some(message));
+        }
+    }
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) {
+        let Some(message) = client.try_read()? else {
+            continue;
+        };
+        if !matches!(message.any_obs_server_message()?, ServerMessage::Event(_)) {
+            continue;
+        }
+        if filter.matches(&message.obs_message_data::<m::AnyEvent>()?) {
+            return Ok(Some(message));
+        }
+        recent.push_back(message);
+    }
+    Ok(None)
+}
+
+fn predicates(
+    conditions: &[String],
+    variables: &HashMap<String, Value>,
+) -> anyhow::Result<Vec<Predicate>> {
+    conditions
+        .iter()
+        .map(|text| Predicate::parse(&fill_text(text, variables)?).map_err(anyhow::Error::msg))
+        .collect()
+}
+
+/// Fills the placeholders in a predicate.
+fn fill_text(text: &str, variables: &HashMap<String, Value>) -> anyhow::Result<String> {
+    Ok(
+        match fill_placeholders(&Value::String(text.to_owned()), variables)? {
+            Value::String(text) => text,
+            value => value.to_string(),
+        },
+    )
+}
+
+fn seconds_to_duration(seconds: f64) -> Result<Duration, String> {
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration {seconds}"))
+}
@@ -0,0 +1,62 @@
+//! `influencer wait-for`: blocks until a matching event occurs, optionally
+//! after sending a request that is expected to cause it.
+
+use crate::filter::EventFilter;
+
+use influencer::{
+    client::Client,
+    message::{self as m, ServerMessage, WsMessageExt as _},
+};
+use std::time::{Duration, Instant};
+use tungstenite::Message as WsMessage;
+
+/// Exit code for giving up, as used by timeout(1).
+pub const EXIT_TIMEOUT: i32 = 124;
+
+/// Sends `request` if given, then returns the first event matching `filter`,
+/// or `None` on timeout. Events arriving before the response count, since
+/// the client is already subscribed when the request is sent.
+pub fn run(
+    client: &mut Client,
+    filter: &EventFilter,
+    request: Option<WsMessage>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<Option<WsMessage>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut pending = request.is_some();
+    if let Some(request) = request {
+        client.send(request)?;
+    }
+    let mut matched = None;
+    while pending || matched.is_none() {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(None);
+        }
+        let Some(message) = client.try_read()? else {
+            continue;
+        };
+        match message.any_obs_server_message()? {
+            ServerMessage::Event(_)
+                if matched.is_none()
+                    && filter.matches(&message.obs_message_data::<m::AnyEvent>()?) =>
+            {
+                matched = Some(message);
+            }
+            ServerMessage::Response(_) => {
+                let response = message.obs_message_data::<m::AnyResponse>()?;
+                let status = response.request_status;
+                if !status.result {
+                    anyhow::bail!(
+                        "{} failed with code {}: {}",
+                        response.request_type,
+                        status.code,
+                        status.comment.unwrap_or("no comment")
+                    );
+                }
+                pending = false;
+            }
+            _ => {}
+        }
+    }
+    Ok(matched)
+}
@@ -0,0 +1,27 @@
+#!/bin/sh
+# Generated by influencer bindings export --format gnome
+# Keeps the custom shortcuts that weren't set up by influencer
+others=$(gsettings get org.gnome.settings-daemon.plugins.media-keys custom-keybindings \
+  | sed -e 's/^@as //' -e 's/[][]//g' | tr ',' '\n' \
+  | sed -e 's/^ *//' -e '/\/influencer[0-9]*\/.$/d' -e '/^$/d' | paste -s -d , -)
+gsettings set org.gnome.settings-daemon.plugins.media-keys custom-keybindings "[${others:+$others, }'/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer1/', '/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer2/', '/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer3/', '/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer4/', '/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer5/']"
+
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer1/ name "'mute-mic'"
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer1/ command ''\''influencer request SetInputMute \'\''{"inputMuted":true,"inputName":"Mic"}\'\'''\'''
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer1/ binding "'<Super><Shift>m'"
+
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer2/ name ''\''scene name=Mom\'\''s "Cam" #2 $HOME 100%'\'''
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer2/ command ''\''influencer request SetCurrentProgramScene \'\''{"sceneName":"Mom\'\''\\\'\''\'\''s \\"Cam\\" #2 $HOME 100%"}\'\'''\'''
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer2/ binding "'<Super>F9'"
+
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer3/ name "'volume db=-10 input=Desktop Audio'"
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer3/ command ''\''influencer request SetInputVolume \'\''{"inputName":"Desktop Audio","inputVolumeDb":-10}\'\'''\'''
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer3/ binding "'<Control><Alt>minus'"
+
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer4/ name "'brb'"
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer4/ command ''\''influencer --profile studio batch --halt-on-failure \'\''[{"requestData":{"sceneName":"BRB"},"requestType":"SetCurrentProgramScene"},{"requestData":{"inputMuted":true,"inputName":"Mic"},"requestType":"SetInputMute"}]\'\'''\'''
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer4/ binding "'<Super>b'"
+
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer5/ name "'save-replay'"
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer5/ command "'influencer request SaveReplayBuffer'"
+gsettings set org.gnome.settings-daemon.plugins.media-keys.custom-keybinding:/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer5/ binding "'Print'"
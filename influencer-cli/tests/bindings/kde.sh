@@ -0,0 +1,54 @@
+#!/bin/sh
+# Generated by influencer bindings export --format kde
+# Log out and back in afterwards for the shortcuts to take effect
+mkdir -p ~/.local/share/applications
+
+cat > ~/.local/share/applications/influencer-1.desktop <<'EOF'
+[Desktop Entry]
+Type=Application
+Name=mute-mic
+Exec=influencer request SetInputMute "{\\"inputMuted\\":true,\\"inputName\\":\\"Mic\\"}"
+NoDisplay=true
+X-KDE-Shortcuts=Meta+Shift+M
+EOF
+kwriteconfig6 --file kglobalshortcutsrc --group services --group influencer-1.desktop --key _launch Meta+Shift+M
+
+cat > ~/.local/share/applications/influencer-2.desktop <<'EOF'
+[Desktop Entry]
+Type=Application
+Name=scene name=Mom's "Cam" #2 $HOME 100%
+Exec=influencer request SetCurrentProgramScene "{\\"sceneName\\":\\"Mom's \\\\\\"Cam\\\\\\" #2 \\$HOME 100%%\\"}"
+NoDisplay=true
+X-KDE-Shortcuts=Meta+F9
+EOF
+kwriteconfig6 --file kglobalshortcutsrc --group services --group influencer-2.desktop --key _launch Meta+F9
+
+cat > ~/.local/share/applications/influencer-3.desktop <<'EOF'
+[Desktop Entry]
+Type=Application
+Name=volume db=-10 input=Desktop Audio
+Exec=influencer request SetInputVolume "{\\"inputName\\":\\"Desktop Audio\\",\\"inputVolumeDb\\":-10}"
+NoDisplay=true
+X-KDE-Shortcuts=Ctrl+Alt+minus
+EOF
+kwriteconfig6 --file kglobalshortcutsrc --group services --group influencer-3.desktop --key _launch Ctrl+Alt+minus
+
+cat > ~/.local/share/applications/influencer-4.desktop <<'EOF'
+[Desktop Entry]
+Type=Application
+Name=brb
+Exec=influencer --profile studio batch --halt-on-failure "[{\\"requestData\\":{\\"sceneName\\":\\"BRB\\"},\\"requestType\\":\\"SetCurrentProgramScene\\"},{\\"requestData\\":{\\"inputMuted\\":true,\\"inputName\\":\\"Mic\\"},\\"requestType\\":\\"SetInputMute\\"}]"
+NoDisplay=true
+X-KDE-Shortcuts=Meta+B
+EOF
+kwriteconfig6 --file kglobalshortcutsrc --group services --group influencer-4.desktop --key _launch Meta+B
+
+cat > ~/.local/share/applications/influencer-5.desktop <<'EOF'
+[Desktop Entry]
+Type=Application
+Name=save-replay
+Exec=influencer request SaveReplayBuffer
+NoDisplay=true
+X-KDE-Shortcuts=Print
+EOF
+kwriteconfig6 --file kglobalshortcutsrc --group services --group influencer-5.desktop --key _launch Print
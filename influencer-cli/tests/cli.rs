@@ -0,0 +1,1908 @@
+use influencer::{
+    auth::{AuthMachine, MessageStream as _},
+    message::{self as m, IntoWsMessageJson as _, WsMessageExt as _},
+    record::{Frame, Replay},
+};
+use influencer_mock::{MockServer, Script, Trigger};
+use serde_json::{Value, json};
+use std::{
+    io::{BufRead, BufReader, Write as _},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+fn influencer(server: &MockServer) -> Command {
+    let mut command = influencer_unconfigured();
+    command.args(["--host", "127.0.0.1", "--port"]);
+    command.arg(server.port().to_string());
+    command.arg("--compact");
+    command
+}
+
+/// The binary, with no connection settings from the environment.
+fn influencer_unconfigured() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_influencer"));
+    for var in [
+        "OBS_WS_HOST",
+        "OBS_WS_PORT",
+        "OBS_WS_PASSWORD",
+        "INFLUENCER_CONFIG",
+        "INFLUENCER_PROFILE",
+    ] {
+        command.env_remove(var);
+    }
+    // Never read the user's config file
+    command.env("XDG_CONFIG_HOME", temp_path("config"));
+    // Never use a daemon that happens to be running
+    command.env("INFLUENCER_SOCKET", temp_path("no-daemon.sock"));
+    command
+}
+
+/// A path in the temporary directory that is unique to this test run.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("influencer-test-{}-{name}", std::process::id()))
+}
+
+fn run(command: &mut Command) -> Value {
+    let output = command.output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "influencer failed: {stderr}");
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn request() {
+    let version = json!({"obsVersion": "31.0.0", "rpcVersion": 1});
+    let script = Script::new()
+        .password("p4ssw0rd")
+        .respond("GetVersion", Some(version.clone()));
+    let server = MockServer::start(script).unwrap();
+    let response =
+        run(influencer(&server).args(["--password", "p4ssw0rd", "request", "GetVersion"]));
+    assert_eq!(response["requestType"], "GetVersion");
+    assert_eq!(response["requestStatus"]["result"], true);
+    assert_eq!(response["responseData"], version);
+}
+
+#[test]
+fn request_with_data() {
+    let script = Script::new().respond("SetInputVolume", None);
+    let server = MockServer::start(script).unwrap();
+    let data = json!({"inputName": "Desktop Audio", "inputVolumeDb": -10});
+    run(influencer(&server).args(["request", "SetInputVolume", &data.to_string()]));
+    let received = server.received();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0]["requestType"], "SetInputVolume");
+    assert_eq!(received[0]["requestData"], data);
+}
+
+#[test]
+fn request_data_options() {
+    let script = Script::new()
+        .respond("SetInputSettings", None)
+        .respond("GetVersion", None);
+    let server = MockServer::start(script).unwrap();
+    let path = temp_path("data.json");
+    std::fs::write(&path, r#"{"inputName": "Title", "overlay": true}"#).unwrap();
+    run(influencer(&server)
+        .args(["request", "SetInputSettings", "--data-file"])
+        .arg(&path)
+        .args(["--set", "inputSettings.text=3"])
+        .args(["--set-json", "overlay=false", "--set", "inputName=Subtitle"]));
+    let _ = std::fs::remove_file(&path);
+
+    let mut child = influencer(&server)
+        .args(["request", "SetInputSettings", "--data-file", "-"])
+        .args(["--set-json", "inputSettings={\"text\": 3}"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(br#"{"inputName": "Title"}"#).unwrap();
+    drop(stdin);
+    assert!(child.wait().unwrap().success());
+
+    run(influencer(&server).args([
+        "batch",
+        "--set",
+        "0.requestType=GetVersion",
+        "--set",
+        "1.requestType=SetInputSettings",
+        "--set",
+        "1.requestData.inputName=Title",
+    ]));
+    let output = influencer(&server)
+        .args([
+            "request",
+            "GetVersion",
+            "{}",
+            "--set",
+            "a.b=1",
+            "--set",
+            "a.b.c=2",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    let received = server.received();
+    assert_eq!(received.len(), 3);
+    assert_eq!(
+        received[0]["requestData"],
+        json!({"inputName": "Subtitle", "overlay": false, "inputSettings": {"text": "3"}})
+    );
+    assert_eq!(
+        received[1]["requestData"],
+        json!({"inputName": "Title", "inputSettings": {"text": 3}})
+    );
+    assert_eq!(
+        received[2]["requests"],
+        json!([
+            {"requestType": "GetVersion"},
+            {"requestType": "SetInputSettings", "requestData": {"inputName": "Title"}},
+        ])
+    );
+}
+
+#[test]
+fn output_formats() {
+    let scenes = json!({
+        "currentProgramSceneName": "Game",
+        "scenes": [
+            {"sceneIndex": 1, "sceneName": "Game, 2"},
+            {"sceneIndex": 0, "sceneName": "BRB"},
+        ],
+    });
+    let script = Script::new().respond("GetSceneList", Some(scenes)).fail(
+        "StopStream",
+        501,
+        "Stream is not active.",
+    );
+    let server = MockServer::start(script).unwrap();
+    let output = |args: &[&str]| {
+        let output = influencer(&server).args(args).output().unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let scene_list = ["request", "GetSceneList"];
+
+    let raw = [
+        "--field",
+        "responseData.currentProgramSceneName",
+        "-o",
+        "raw",
+    ];
+    assert_eq!(output(&[&raw[..], &scene_list].concat()), "Game\n");
+    let pointer = ["--field", "/responseData/scenes/1/sceneIndex"];
+    assert_eq!(output(&[&pointer[..], &scene_list].concat()), "0\n");
+    let missing = ["--field", ".responseData.nothing"];
+    assert_eq!(output(&[&missing[..], &scene_list].concat()), "null\n");
+    assert_eq!(
+        output(&[&["-o", "tsv"][..], &scene_list].concat()),
+        "sceneIndex\tsceneName\n1\tGame, 2\n0\tBRB\n"
+    );
+    assert_eq!(
+        output(&[&["-o", "csv"][..], &scene_list].concat()),
+        "sceneIndex,sceneName\n1,\"Game, 2\"\n0,BRB\n"
+    );
+    assert_eq!(
+        output(&[&["-o", "yaml", "--field", "responseData"][..], &scene_list].concat()),
+        "currentProgramSceneName: Game\n\
+        scenes:\n\
+        - sceneIndex: 1\n  \
+          sceneName: Game, 2\n\
+        - sceneIndex: 0\n  \
+          sceneName: BRB\n"
+    );
+    assert_eq!(
+        output(&["-o", "summary", "request", "StopStream"]),
+        "✗ StopStream failed with code 501: Stream is not active.\n"
+    );
+    assert_eq!(
+        output(&[&["-o", "summary"][..], &scene_list].concat()),
+        "✓ GetSceneList\n  \
+        currentProgramSceneName: Game\n  \
+        scenes:\n    \
+          - sceneIndex: 1\n      \
+            sceneName: Game, 2\n    \
+          - sceneIndex: 0\n      \
+            sceneName: BRB\n"
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let script = Script::new()
+        .respond("GetVersion", None)
+        .fail("StopStream", 501, "Stream is not active.")
+        .fail("CreateScene", 601, "A scene with that name already exists.");
+    let server = MockServer::start(script).unwrap();
+    let status = |args: &[&str]| influencer(&server).args(args).output().unwrap().status;
+    assert_eq!(status(&["request", "GetVersion"]).code(), Some(0));
+    assert_eq!(status(&["request", "StopStream"]).code(), Some(51));
+    assert_eq!(status(&["request", "CreateScene"]).code(), Some(61));
+    // No fixture, so unknown (204)
+    assert_eq!(status(&["request", "Nonsense"]).code(), Some(24));
+    assert_eq!(
+        status(&["--no-fail", "request", "StopStream"]).code(),
+        Some(0)
+    );
+    let requests = json!([{"requestType": "GetVersion"}, {"requestType": "CreateScene"}]);
+    assert_eq!(status(&["batch", &requests.to_string()]).code(), Some(61));
+}
+
+#[test]
+fn wrong_password() {
+    let server = MockServer::start(Script::new().password("p4ssw0rd")).unwrap();
+    let output = influencer(&server)
+        .args(["--password", "hunter2", "request", "GetVersion"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(server.received().is_empty());
+}
+
+#[test]
+fn request_unconfirmed_close() {
+    let script = Script::new().respond("GetVersion", None).ignore_close();
+    let server = MockServer::start(script).unwrap();
+    let output = influencer(&server)
+        .args(["request", "GetVersion"])
+        .output()
+        .unwrap();
+    // The response counts, even if OBS never confirms the close
+    assert!(output.status.success());
+    let response: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(response["requestStatus"]["result"], true);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("Failed to close the connection: "),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn batch() {
+    let script = Script::new()
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})))
+        .fail("StopStream", 501, "Stream is not active.");
+    let server = MockServer::start(script).unwrap();
+    let requests = json!([
+        {"requestType": "GetVersion", "requestId": "a"},
+        {"requestType": "StopStream", "requestId": "b"},
+        {"requestType": "GetVersion", "requestId": "c"},
+    ]);
+    let output = influencer(&server)
+        .args(["batch", "--halt-on-failure", &requests.to_string()])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(51));
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "StopStream failed with code 501: Stream is not active.\n"
+    );
+    let response: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let results = response["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["requestId"], "a");
+    assert_eq!(results[0]["responseData"]["rpcVersion"], 1);
+    assert_eq!(results[1]["requestStatus"]["code"], 501);
+    assert_eq!(server.received()[0]["haltOnFailure"], true);
+}
+
+#[test]
+fn events() {
+    let script = Script::new()
+        .event(
+            Trigger::After(Duration::from_millis(50)),
+            "CurrentProgramSceneChanged",
+            4,
+            Some(json!({"sceneName": "BRB"})),
+        )
+        // Not subscribed to (Outputs), so never printed
+        .event(
+            Trigger::After(Duration::from_millis(60)),
+            "RecordStateChanged",
+            64,
+            None,
+        )
+        .event(
+            Trigger::After(Duration::from_millis(70)),
+            "ExitStarted",
+            1,
+            None,
+        );
+    let server = MockServer::start(script).unwrap();
+    let mut child = influencer(&server)
+        .args(["events", "5"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next_event =
+        || -> Value { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+    let event = next_event();
+    assert_eq!(event["eventType"], "CurrentProgramSceneChanged");
+    assert_eq!(event["eventData"]["sceneName"], "BRB");
+    assert_eq!(next_event()["eventType"], "ExitStarted");
+    server.emit("SceneNameChanged", 4, Some(json!({"sceneName": "Live"})));
+    assert_eq!(next_event()["eventType"], "SceneNameChanged");
+    child.kill().unwrap();
+    child.wait().unwrap();
+}
+
+#[test]
+fn events_filtered() {
+    let scene = |name: &str| Some(json!({"sceneName": name}));
+    let script = Script::new()
+        .event(
+            Trigger::After(Duration::from_millis(50)),
+            "CurrentProgramSceneChanged",
+            4,
+            scene("Game"),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(60)),
+            "SceneNameChanged",
+            4,
+            scene("BRB"),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(70)),
+            "InputMuteStateChanged",
+            8,
+            Some(json!({"inputName": "BRB", "inputMuted": true})),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(80)),
+            "CurrentProgramSceneChanged",
+            4,
+            scene("BRB"),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(90)),
+            "CurrentPreviewSceneChanged",
+            4,
+            scene("BRB"),
+        );
+    let server = MockServer::start(script).unwrap();
+    let output = influencer(&server)
+        .args([
+            "events",
+            "--type",
+            "Current*SceneChanged",
+            "--type",
+            "Input*",
+        ])
+        .args(["--exclude-type", "InputMuteStateChanged"])
+        .args(["--where", r#".sceneName == "BRB""#, "--count", "2"])
+        .arg("--with-timestamp")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let events: Vec<Value> = serde_json::Deserializer::from_slice(&output.stdout)
+        .into_iter()
+        .map(Result::unwrap)
+        .collect();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["eventType"], "CurrentProgramSceneChanged");
+    assert_eq!(events[1]["eventType"], "CurrentPreviewSceneChanged");
+    assert!(
+        events
+            .iter()
+            .all(|event| event["eventData"] == scene("BRB").unwrap())
+    );
+    assert!(events[0]["receivedAt"].is_string());
+
+    let output = influencer(&server)
+        .args([
+            "events",
+            "--where",
+            ".inputMuted == false",
+            "--timeout",
+            "0.3",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    let output = influencer(&server)
+        .args(["events", "--where", "sceneName == BRB"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn wait_for() {
+    let state =
+        |active: bool, state: &str| Some(json!({"outputActive": active, "outputState": state}));
+    let started = state(true, "OBS_WEBSOCKET_OUTPUT_STARTED");
+    let script = Script::new()
+        .respond("StartRecord", None)
+        .fail("StartStream", 500, "Output already running")
+        .event(
+            Trigger::OnRequest("StartRecord".into()),
+            "RecordStateChanged",
+            64,
+            state(false, "OBS_WEBSOCKET_OUTPUT_STARTING"),
+        )
+        .event(
+            Trigger::OnRequest("StartRecord".into()),
+            "RecordStateChanged",
+            64,
+            started.clone(),
+        )
+        // High-volume, so only received when asked for specifically
+        .event(
+            Trigger::After(Duration::from_millis(50)),
+            "InputVolumeMeters",
+            1 << 16,
+            Some(json!({"inputs": []})),
+        );
+    let server = MockServer::start(script).unwrap();
+    let event = run(influencer(&server)
+        .args(["wait-for", "RecordStateChanged", "--request", "StartRecord"])
+        .args(["--where", ".outputActive", "--timeout", "5"]));
+    assert_eq!(event["eventData"], started.unwrap());
+
+    let event = run(influencer(&server).args(["wait-for", "InputVolumeMeters", "--timeout", "5"]));
+    assert_eq!(event["eventType"], "InputVolumeMeters");
+    // Wildcards leave out high-volume events
+    let output = influencer(&server)
+        .args(["wait-for", "InputVolume*", "--timeout", "0.3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(124));
+
+    let output = influencer(&server)
+        .args(["wait-for", "ExitStarted", "--timeout", "0.3"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(124));
+    assert!(output.stdout.is_empty());
+
+    let output = influencer(&server)
+        .args(["wait-for", "StreamStateChanged", "--request", "StartStream"])
+        .args(["--timeout", "5"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_ne!(output.status.code(), Some(124));
+}
+
+/// Waits until `path` has `lines` lines, and returns them.
+fn wait_for_lines(path: &std::path::Path, lines: usize) -> Vec<String> {
+    for _ in 0..100 {
+        let text = std::fs::read_to_string(path).unwrap_or_default();
+        if text.lines().count() >= lines {
+            return text.lines().map(str::to_owned).collect();
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    panic!("{} never got {lines} lines", path.display());
+}
+
+#[cfg(unix)]
+#[test]
+fn on() {
+    let scene_changed = |ms, name: &str| {
+        (
+            Trigger::After(Duration::from_millis(ms)),
+            "CurrentProgramSceneChanged",
+            4,
+            // A field named like one of the variables, and an acronym
+            Some(json!({"sceneName": name, "sceneUUID": name.to_lowercase(), "type": "scene"})),
+        )
+    };
+    let script = [
+        scene_changed(50, "Game"),
+        scene_changed(60, "Skip"),
+        scene_changed(70, "BRB"),
+    ]
+    .into_iter()
+    .fold(
+        Script::new(),
+        |script, (trigger, event_type, intent, data)| {
+            script.event(trigger, event_type, intent, data)
+        },
+    );
+    let server = MockServer::start(script).unwrap();
+
+    let out = temp_path("on.txt");
+    let mut child = influencer(&server)
+        .args([
+            "on",
+            "CurrentProgramSceneChanged",
+            "--where",
+            ".sceneName != Skip",
+        ])
+        .args(["--", "sh", "-c"])
+        .arg(concat!(
+            r#"echo "$OBS_EVENT_TYPE $OBS_EVENT_DATA_TYPE $OBS_EVENT_DATA_SCENE_NAME "#,
+            r#"$OBS_EVENT_DATA_SCENE_UUID $(cat)" >> "$0""#
+        ))
+        .arg(&out)
+        .spawn()
+        .unwrap();
+    let lines = wait_for_lines(&out, 2);
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(lines.len(), 2);
+    for (line, name) in lines.iter().zip(["Game", "BRB"]) {
+        let (variables, json) = line.split_at(line.find('{').unwrap());
+        assert_eq!(
+            variables,
+            format!(
+                "CurrentProgramSceneChanged scene {name} {} ",
+                name.to_lowercase()
+            )
+        );
+        let event: Value = serde_json::from_str(json).unwrap();
+        assert_eq!(event["eventData"]["sceneName"], name);
+    }
+    let _ = std::fs::remove_file(&out);
+
+    // Only the last of the three is left once they settle
+    let out = temp_path("on-debounce.txt");
+    let mut child = influencer(&server)
+        .args(["on", "*SceneChanged", "--debounce", "0.3"])
+        .args([
+            "--",
+            "sh",
+            "-c",
+            r#"echo "$OBS_EVENT_DATA_SCENE_NAME" >> "$0""#,
+        ])
+        .arg(&out)
+        .spawn()
+        .unwrap();
+    let lines = wait_for_lines(&out, 1);
+    std::thread::sleep(Duration::from_millis(500));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(lines, ["BRB"]);
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "BRB\n");
+    let _ = std::fs::remove_file(&out);
+}
+
+#[test]
+fn rules() {
+    let script = Script::new()
+        .respond("SetInputMute", None)
+        .respond("StartReplayBuffer", None)
+        .respond("SetSourceFilterEnabled", None)
+        .event(
+            Trigger::After(Duration::from_millis(50)),
+            "CurrentProgramSceneChanged",
+            4,
+            Some(json!({"sceneName": "Game"})),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(60)),
+            "CurrentProgramSceneChanged",
+            4,
+            Some(json!({"sceneName": "BRB"})),
+        )
+        .event(
+            Trigger::After(Duration::from_millis(70)),
+            "InputMuteStateChanged",
+            8,
+            Some(json!({"inputName": "Mic", "inputMuted": true})),
+        );
+    let server = MockServer::start(script).unwrap();
+    let rules = temp_path("rules.toml");
+    std::fs::write(
+        &rules,
+        r#"
+[[rule]]
+name = "brb"
+when = "CurrentProgramSceneChanged"
+where = ['.sceneName == "BRB"']
+halt_on_failure = true
+then = [
+    { request = "SetInputMute", data = { inputName = "Mic", inputMuted = true } },
+    { request = "StartReplayBuffer" },
+]
+
+[[rule]]
+when = "InputMuteStateChanged"
+capture = { muted = ".inputMuted" }
+then = [
+    { request = "SetSourceFilterEnabled", data = { sourceName = "{inputName}", filterName = "Muted", filterEnabled = "{muted}" } },
+]
+"#,
+    )
+    .unwrap();
+
+    let mut child = influencer(&server)
+        .arg("rules")
+        .arg(&rules)
+        .arg("--dry-run")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let mut next_action =
+        || -> Value { serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap() };
+    let action = next_action();
+    assert_eq!(action["rule"], "brb");
+    assert_eq!(action["request"]["haltOnFailure"], true);
+    assert_eq!(
+        action["request"]["requests"][1]["requestType"],
+        "StartReplayBuffer"
+    );
+    let action = next_action();
+    assert_eq!(action["rule"], "#2");
+    assert_eq!(action["request"]["requestType"], "SetSourceFilterEnabled");
+    assert_eq!(
+        action["request"]["requestData"],
+        json!({"sourceName": "Mic", "filterName": "Muted", "filterEnabled": true})
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(server.received().is_empty());
+
+    let mut child = influencer(&server)
+        .arg("rules")
+        .arg(&rules)
+        .spawn()
+        .unwrap();
+    for _ in 0..100 {
+        if server.received().len() >= 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let received = server.received();
+    assert_eq!(received.len(), 2);
+    assert_eq!(received[0]["requests"][0]["requestType"], "SetInputMute");
+    assert_eq!(received[1]["requestData"]["filterEnabled"], true);
+    let _ = std::fs::remove_file(&rules);
+
+    // The same as YAML
+    let rules = temp_path("rules.yaml");
+    std::fs::write(
+        &rules,
+        r#"
+rule:
+  - name: brb
+    when: CurrentProgramSceneChanged
+    where: ['.sceneName == "BRB"']
+    then:
+      - request: SetInputMute
+        data: { inputName: Mic, inputMuted: true }
+"#,
+    )
+    .unwrap();
+    let mut child = influencer(&server)
+        .arg("rules")
+        .arg(&rules)
+        .arg("--dry-run")
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let action: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert_eq!(action["rule"], "brb");
+    assert_eq!(
+        action["request"]["requestData"],
+        json!({"inputName": "Mic", "inputMuted": true})
+    );
+    let _ = std::fs::remove_file(&rules);
+}
+
+#[test]
+fn rules_reconnect() {
+    let script = Script::new().hang("SetInputMute").event(
+        Trigger::After(Duration::from_millis(50)),
+        "CurrentProgramSceneChanged",
+        4,
+        Some(json!({"sceneName": "BRB"})),
+    );
+    let server = MockServer::start(script).unwrap();
+    let rules = temp_path("rules-reconnect.toml");
+    std::fs::write(
+        &rules,
+        r#"
+[[rule]]
+name = "brb"
+when = "CurrentProgramSceneChanged"
+then = [{ request = "SetInputMute", data = { inputName = "Mic", inputMuted = true } }]
+"#,
+    )
+    .unwrap();
+    let mut child = influencer(&server)
+        .arg("rules")
+        .arg(&rules)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for_received(&server, 1);
+    // The request is lost with the connection, and the rule says so
+    server.disconnect();
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    child.kill().unwrap();
+    child.wait().unwrap();
+    assert!(
+        line.starts_with("Rule brb: Connection to OBS lost"),
+        "{line}"
+    );
+    let _ = std::fs::remove_file(&rules);
+}
+
+/// Waits a little for `server` to receive `n` requests.
+fn wait_for_received(server: &MockServer, n: usize) {
+    let start = std::time::Instant::now();
+    while server.received().len() < n {
+        assert!(start.elapsed() < Duration::from_secs(5));
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Starts `influencer proxy` in front of `server`, with the client password
+/// "downstream". Returns the proxy process and a command builder for
+/// connecting through it.
+fn proxy(server: &MockServer, args: &[&str]) -> (Child, impl Fn() -> Command + use<>) {
+    let mut proxy = influencer(server)
+        .args(["--password", "upstream", "proxy", "--listen", "127.0.0.1:0"])
+        .args(["--client-password", "downstream"])
+        .args(args)
+        .env_remove("INFLUENCER_PROXY_PASSWORD")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(proxy.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    let port = line.trim().rsplit(':').next().unwrap().to_owned();
+    // Later messages would fail to print if nobody was reading
+    std::thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+    let through_proxy = move || {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_influencer"));
+        command.env_remove("OBS_WS_HOST").env_remove("OBS_WS_PORT");
+        command.env("INFLUENCER_SOCKET", temp_path("no-daemon.sock"));
+        command.args(["--host", "127.0.0.1", "--port", &port, "--compact"]);
+        command.args(["--password", "downstream"]);
+        command
+    };
+    (proxy, through_proxy)
+}
+
+#[test]
+fn proxy_fan_out() {
+    let script = Script::new()
+        .password("upstream")
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})));
+    let server = MockServer::start(script).unwrap();
+    let (proxy, through_proxy) = proxy(&server, &[]);
+
+    let mut events = through_proxy()
+        .args(["events", "4"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let response = run(through_proxy().args(["request", "GetVersion"]));
+    assert_eq!(response["requestId"], ":3");
+    assert_eq!(response["responseData"]["rpcVersion"], 1);
+    // The request id was rewritten on the way upstream
+    assert_ne!(server.received()[0]["requestId"], ":3");
+
+    let mut lines = BufReader::new(events.stdout.take().unwrap()).lines();
+    // Not subscribed to by any client
+    server.emit("RecordStateChanged", 64, None);
+    server.emit("SceneNameChanged", 4, Some(json!({"sceneName": "Live"})));
+    let event: Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(event["eventType"], "SceneNameChanged");
+
+    for mut child in [events, proxy] {
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn proxy_policy() {
+    let policy = temp_path("policy.toml");
+    std::fs::write(
+        &policy,
+        r#"
+        default = "deny"
+
+        [[rule]]
+        action = "deny"
+        requests = ["GetInputSettings"]
+        fields.inputName = "Secret *"
+
+        [[rule]]
+        action = "allow"
+        requests = ["Get*"]
+
+        [[rule]]
+        action = "allow"
+        requests = ["SetCurrentProgramScene"]
+        fields.sceneName = "Game *"
+        "#,
+    )
+    .unwrap();
+    let script = Script::new()
+        .password("upstream")
+        .respond("GetVersion", None)
+        .respond("GetInputSettings", None)
+        .respond("SetCurrentProgramScene", None)
+        .respond("StopStream", None);
+    let server = MockServer::start(script).unwrap();
+    let (mut proxy, through_proxy) = proxy(&server, &["--policy", policy.to_str().unwrap()]);
+
+    let request = |request_type: &str, data: Value| {
+        let mut command = through_proxy();
+        command.args(["--no-fail", "request", request_type, &data.to_string()]);
+        run(&mut command)["requestStatus"]["result"].clone()
+    };
+    assert_eq!(request("GetVersion", json!({})), true);
+    assert_eq!(request("StopStream", json!({})), false);
+    let scene = |name: &str| json!({"sceneName": name});
+    assert_eq!(request("SetCurrentProgramScene", scene("Game 2")), true);
+    assert_eq!(request("SetCurrentProgramScene", scene("BRB")), false);
+    // Missing fields match deny rules, but not allow rules
+    assert_eq!(request("SetCurrentProgramScene", json!({})), false);
+    let input = |name: &str| json!({"inputName": name});
+    assert_eq!(request("GetInputSettings", input("Mic")), true);
+    assert_eq!(request("GetInputSettings", input("Secret stash")), false);
+    assert_eq!(
+        request("GetInputSettings", json!({"inputUuid": "1"})),
+        false
+    );
+
+    let requests = json!([
+        {"requestType": "GetVersion"},
+        {"requestType": "StopStream"},
+    ]);
+    let response = run(through_proxy().args(["--no-fail", "batch", &requests.to_string()]));
+    let results = response["results"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert!(
+        results
+            .iter()
+            .all(|result| result["requestStatus"]["result"] == false)
+    );
+
+    let received: Vec<_> = server
+        .received()
+        .iter()
+        .map(|r| r["requestType"].clone())
+        .collect();
+    assert_eq!(
+        received,
+        [
+            json!("GetVersion"),
+            json!("SetCurrentProgramScene"),
+            json!("GetInputSettings")
+        ]
+    );
+
+    proxy.kill().unwrap();
+    proxy.wait().unwrap();
+    std::fs::remove_file(policy).unwrap();
+}
+
+#[test]
+fn proxy_reconnect() {
+    let script = Script::new()
+        .password("upstream")
+        .respond("GetVersion", None)
+        .hang("GetStats");
+    let server = MockServer::start(script).unwrap();
+    let (mut proxy, through_proxy) = proxy(&server, &[]);
+
+    // OBS gets stuck on the request, then goes away
+    let request = through_proxy()
+        .args(["--no-fail", "request", "GetStats"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for_received(&server, 1);
+    server.disconnect();
+    let output = request.wait_with_output().unwrap();
+    let response: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(response["requestStatus"]["result"], false);
+    let comment = response["requestStatus"]["comment"].as_str().unwrap();
+    assert!(comment.starts_with("Connection to OBS lost"), "{comment}");
+
+    // Requests after the reconnect go through
+    let response = run(through_proxy().args(["request", "GetVersion"]));
+    assert_eq!(response["requestStatus"]["result"], true);
+    assert_eq!(server.identified(), 2);
+
+    proxy.kill().unwrap();
+    proxy.wait().unwrap();
+}
+
+#[test]
+fn record_and_replay() {
+    let recording = temp_path("recording.jsonl");
+    let script = Script::new()
+        .password("p4ssw0rd")
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})));
+    let server = MockServer::start(script).unwrap();
+    let record = || {
+        let mut command = influencer(&server);
+        command.args(["--password", "p4ssw0rd", "--record"]);
+        command.arg(&recording);
+        run(command.args(["request", "GetVersion"]));
+    };
+    record();
+
+    let load = || {
+        let file = BufReader::new(std::fs::File::open(&recording).unwrap());
+        Replay::from_reader(file).unwrap()
+    };
+    let mut replay = load();
+    let auth = AuthMachine::new(&mut replay, Some("p4ssw0rd"), Some(0));
+    auth.drive().ready().unwrap();
+    let request = m::Request::<()> {
+        request_type: "GetVersion",
+        request_id: ":3",
+        request_data: None,
+    };
+    replay
+        .write(request.into_ws_message_json().unwrap())
+        .unwrap();
+    let response = replay.read_data().unwrap();
+    let response = response.obs_message_data::<m::AnyResponse>().unwrap();
+    assert_eq!(response.response_data.unwrap()["rpcVersion"], 1);
+    // Only the closing handshake is left
+    assert!(
+        replay
+            .remaining()
+            .all(|entry| matches!(entry.frame, Frame::Close { .. }))
+    );
+
+    // Sending something else than what was recorded
+    let mut replay = load();
+    assert!(
+        AuthMachine::new(&mut replay, Some("hunter2"), Some(0))
+            .drive()
+            .ready()
+            .is_err()
+    );
+
+    // Recording again appends a second session
+    record();
+    let file = BufReader::new(std::fs::File::open(&recording).unwrap());
+    let sessions = Replay::sessions(file).unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(
+        sessions[0].remaining().count(),
+        sessions[1].remaining().count()
+    );
+    std::fs::remove_file(recording).unwrap();
+}
+
+#[test]
+fn shell() {
+    let script = Script::new()
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})))
+        .respond("SetInputMute", None);
+    let server = MockServer::start(script).unwrap();
+    let mut child = influencer(&server)
+        .arg("shell")
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "GetVersion").unwrap();
+    writeln!(
+        stdin,
+        r#"SetInputMute {{"inputName": "Mic", "inputMuted": true}}"#
+    )
+    .unwrap();
+    writeln!(stdin, "SetInputMute {{").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let responses: Vec<Value> = output
+        .stdout
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["responseData"]["rpcVersion"], 1);
+    assert_eq!(responses[1]["requestType"], "SetInputMute");
+    assert_eq!(server.received()[1]["requestData"]["inputName"], "Mic");
+}
+
+#[test]
+fn shell_reconnect() {
+    let script = Script::new()
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})))
+        .hang("GetStats");
+    let server = MockServer::start(script).unwrap();
+    let mut child = influencer(&server)
+        .arg("shell")
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    // OBS gets stuck on the request, then goes away
+    writeln!(stdin, "GetStats").unwrap();
+    wait_for_received(&server, 1);
+    server.disconnect();
+    writeln!(stdin, "GetVersion").unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let responses: Vec<Value> = output
+        .stdout
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(responses.len(), 2);
+    assert_eq!(responses[0]["requestStatus"]["result"], false);
+    let comment = responses[0]["requestStatus"]["comment"].as_str().unwrap();
+    assert!(comment.starts_with("Connection to OBS lost"), "{comment}");
+    assert_eq!(responses[1]["responseData"]["rpcVersion"], 1);
+    assert_eq!(server.identified(), 2);
+}
+
+#[test]
+fn pipe() {
+    let script = Script::new()
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})))
+        .event(
+            Trigger::OnRequest("SetCurrentProgramScene".into()),
+            "CurrentProgramSceneChanged",
+            4,
+            Some(json!({"sceneName": "BRB"})),
+        )
+        .respond("SetCurrentProgramScene", None);
+    let server = MockServer::start(script).unwrap();
+    let mut child = influencer(&server)
+        .args(["pipe", "--events"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    let lines = [
+        json!({"requestType": "GetVersion", "requestId": "mine"}),
+        json!({"requestType": "SetCurrentProgramScene", "requestData": {"sceneName": "BRB"}}),
+        json!({"requests": [{"requestType": "GetVersion"}], "requestId": "batch"}),
+        json!({"requestData": {}}),
+    ];
+    for line in lines {
+        writeln!(stdin, "{line}").unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let output: Vec<Value> = output
+        .stdout
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(output.len(), 5);
+    let find = |key: &str, value: &str| output.iter().find(|line| line[key] == value).unwrap();
+    assert_eq!(find("requestId", "mine")["responseData"]["rpcVersion"], 1);
+    // Tagged with the line number
+    assert_eq!(
+        find("requestId", "2")["requestType"],
+        "SetCurrentProgramScene"
+    );
+    assert_eq!(
+        find("requestId", "batch")["results"]
+            .as_array()
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        find("eventType", "CurrentProgramSceneChanged")["eventData"]["sceneName"],
+        "BRB"
+    );
+    assert!(
+        output
+            .iter()
+            .any(|line| line["line"] == 4 && line["error"].is_string())
+    );
+    // Output options don't apply to its NDJSON
+    for args in [["-o", "yaml"], ["--field", "responseData"]] {
+        let output = influencer(&server)
+            .args(args)
+            .arg("pipe")
+            .stdin(Stdio::null())
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("can't be used with pipe"), "{stderr}");
+    }
+}
+
+#[test]
+fn pipe_unanswered() {
+    let script = Script::new().hang("GetVersion");
+    let server = MockServer::start(script).unwrap();
+    let mut child = influencer(&server)
+        .args(["pipe", "--timeout", "0.5"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    writeln!(stdin, "{}", json!({"requestType": "GetVersion"})).unwrap();
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(!output.status.success());
+    let error: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(error, json!({"line": 1, "error": "No response from OBS"}));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("1 request(s) never got a response"),
+        "{stderr}"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon() {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let script = Script::new()
+        .password("p4ssw0rd")
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})));
+    let server = MockServer::start(script).unwrap();
+    let socket = temp_path("daemon.sock");
+    let mut daemon = influencer(&server)
+        .args(["--password", "p4ssw0rd", "daemon"])
+        .env("INFLUENCER_SOCKET", &socket)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(daemon.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Listening on"), "{line}");
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // No password needed, the daemon is already authenticated
+    for _ in 0..3 {
+        let mut command = influencer(&server);
+        command.env("INFLUENCER_SOCKET", &socket);
+        let response = run(command.args(["request", "GetVersion"]));
+        assert_eq!(response["requestId"], ":3");
+        assert_eq!(response["responseData"]["rpcVersion"], 1);
+    }
+    let requests = json!([{"requestType": "GetVersion"}]);
+    let mut command = influencer(&server);
+    command.env("INFLUENCER_SOCKET", &socket);
+    let response = run(command.args(["batch", &requests.to_string()]));
+    assert_eq!(response["results"][0]["responseData"]["rpcVersion"], 1);
+    assert_eq!(server.received().len(), 4);
+    assert_eq!(server.identified(), 1);
+
+    // Not used when asked to connect somewhere else
+    let other = MockServer::start(Script::new().respond("GetVersion", None)).unwrap();
+    let mut command = influencer(&other);
+    command.env("INFLUENCER_SOCKET", &socket);
+    run(command.args(["request", "GetVersion"]));
+    assert_eq!(other.received().len(), 1);
+    assert_eq!(server.received().len(), 4);
+
+    // Interrupting the daemon closes the connection and removes the socket
+    let interrupt = Command::new("kill")
+        .args(["-INT", &daemon.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(interrupt.success());
+    assert_eq!(daemon.wait().unwrap().code(), Some(130));
+    assert_eq!(server.closed(), 1);
+    assert!(!socket.exists());
+    // Falls back to connecting directly
+    let mut command = influencer(&server);
+    command.env("INFLUENCER_SOCKET", &socket);
+    run(command.args(["--password", "p4ssw0rd", "request", "GetVersion"]));
+    assert_eq!(server.identified(), 2);
+}
+
+#[cfg(unix)]
+#[test]
+fn daemon_waits_for_obs() {
+    let port = std::net::TcpListener::bind(("127.0.0.1", 0))
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let socket = temp_path("daemon-wait.sock");
+    let mut daemon = influencer_unconfigured()
+        .args(["--host", "127.0.0.1", "--port", &port.to_string(), "daemon"])
+        .env("INFLUENCER_SOCKET", &socket)
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stderr = BufReader::new(daemon.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Waiting for OBS"), "{line}");
+
+    // OBS starts later
+    let script = Script::new().respond("GetVersion", Some(json!({"rpcVersion": 1})));
+    let server = MockServer::start_on(script, port).unwrap();
+    line.clear();
+    stderr.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Listening on"), "{line}");
+    let mut command = influencer(&server);
+    command.env("INFLUENCER_SOCKET", &socket);
+    let response = run(command.args(["request", "GetVersion"]));
+    assert_eq!(response["responseData"]["rpcVersion"], 1);
+    assert_eq!(server.identified(), 1);
+    daemon.kill().unwrap();
+    daemon.wait().unwrap();
+    let _ = std::fs::remove_file(&socket);
+}
+
+#[test]
+fn profiles() {
+    let script = Script::new()
+        .password("p4ssw0rd")
+        .respond("GetVersion", Some(json!({"rpcVersion": 1})));
+    let server = MockServer::start(script).unwrap();
+    let config = temp_path("profiles.toml");
+    let port = server.port();
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+default_profile = "mock"
+
+[profiles.mock]
+host = "127.0.0.1"
+port = {port}
+password = "p4ssw0rd"
+
+[profiles.wrong]
+host = "127.0.0.1"
+port = {port}
+password = "hunter2"
+
+[profiles.tls]
+host = "127.0.0.1"
+port = {port}
+password = "p4ssw0rd"
+tls = true
+"#
+        ),
+    )
+    .unwrap();
+    let command = || {
+        let mut command = influencer_unconfigured();
+        command.arg("--config").arg(&config);
+        command
+    };
+    let response = run(command().args(["request", "GetVersion"]));
+    assert_eq!(response["responseData"]["rpcVersion"], 1);
+
+    let output = command()
+        .args(["--profile", "wrong", "request", "GetVersion"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    // The environment takes precedence over the profile
+    run(command()
+        .args(["--profile", "wrong", "request", "GetVersion"])
+        .env("OBS_WS_PASSWORD", "p4ssw0rd"));
+    // And the command line over both
+    run(command()
+        .args(["--profile", "wrong", "--password", "p4ssw0rd"])
+        .args(["request", "GetVersion"])
+        .env("OBS_WS_PASSWORD", "hunter2"));
+
+    // The mock doesn't speak TLS
+    let output = command()
+        .args(["--profile", "tls", "request", "GetVersion"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    run(command().args(["--profile", "tls", "--no-tls", "request", "GetVersion"]));
+    let output = command()
+        .args([
+            "--host",
+            "wss://127.0.0.1",
+            "--no-tls",
+            "request",
+            "GetVersion",
+        ])
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--no-tls contradicts"), "{stderr}");
+    // The last of --tls and --no-tls wins
+    run(command()
+        .args(["--profile", "tls", "--tls", "--no-tls"])
+        .args(["request", "GetVersion"]));
+
+    let output = command()
+        .args(["--profile", "missing", "request", "GetVersion"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let _ = std::fs::remove_file(&config);
+}
+
+#[test]
+fn macros() {
+    let script = Script::new()
+        .respond("SetCurrentProgramScene", None)
+        .respond("SetInputMute", None);
+    let server = MockServer::start(script).unwrap();
+    let config = temp_path("macros.toml");
+    std::fs::write(
+        &config,
+        r#"
+[macros.scene]
+description = "Switch to a scene"
+request = "SetCurrentProgramScene"
+data = { sceneName = "{name}" }
+params = { name = "Game" }
+
+[macros.mute]
+request = "SetInputMute"
+data = { inputName = "{input}", inputMuted = "{muted:json}" }
+
+[macros.brb]
+batch = [
+    { requestType = "SetCurrentProgramScene", requestData = { sceneName = "BRB" } },
+    { requestType = "SetInputMute", requestData = { inputName = "Mic", inputMuted = true } },
+]
+halt_on_failure = true
+"#,
+    )
+    .unwrap();
+    let command = || {
+        let mut command = influencer(&server);
+        command.arg("--config").arg(&config);
+        command
+    };
+    run(command().args(["run", "scene"]));
+    run(command().args(["run", "scene", "name=BRB"]));
+    run(command().args(["run", "mute", "input=Mic", "muted=true"]));
+    let response = run(command().args(["run", "brb"]));
+    assert_eq!(response["results"].as_array().unwrap().len(), 2);
+    // Strings stay strings, even if they look like JSON
+    run(command().args(["run", "scene", "name=1"]));
+    let received = server.received();
+    assert_eq!(received[4]["requestData"]["sceneName"], "1");
+    assert_eq!(received[0]["requestData"]["sceneName"], "Game");
+    assert_eq!(received[1]["requestData"]["sceneName"], "BRB");
+    assert_eq!(
+        received[2]["requestData"],
+        json!({"inputName": "Mic", "inputMuted": true})
+    );
+    assert_eq!(received[3]["haltOnFailure"], true);
+    assert_eq!(received[3]["requests"][1]["requestType"], "SetInputMute");
+
+    // Missing and unknown parameters, and invalid JSON, are errors, and
+    // nothing is sent
+    for args in [
+        &["run", "mute", "input=Mic"][..],
+        &["run", "scene", "scene=BRB"],
+        &["run", "mute", "input=Mic", "muted=yes"],
+    ] {
+        let output = command().args(args).output().unwrap();
+        assert!(!output.status.success());
+    }
+    assert_eq!(server.received().len(), 5);
+
+    let output = command().arg("run").output().unwrap();
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        listing,
+        "brb\nmute input muted\nscene name\tSwitch to a scene\n"
+    );
+    let _ = std::fs::remove_file(&config);
+}
+
+/// Compares `bindings export` output against the files in `tests/bindings`.
+/// Run with `UPDATE_GOLDEN=1` to rewrite them instead.
+#[test]
+fn bindings_export() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/bindings");
+    for (format, golden) in [
+        ("sway", "sway.conf"),
+        ("hyprland", "hyprland.conf"),
+        ("kde", "kde.sh"),
+        ("gnome", "gnome.sh"),
+    ] {
+        let output = influencer_unconfigured()
+            .arg("--config")
+            .arg(dir.join("config.toml"))
+            .args(["bindings", "export", "--format", format])
+            .output()
+            .unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "influencer failed: {stderr}");
+        let output = String::from_utf8(output.stdout).unwrap();
+        let golden = dir.join(golden);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&golden, &output).unwrap();
+        }
+        assert_eq!(
+            output,
+            std::fs::read_to_string(&golden).unwrap(),
+            "{format}"
+        );
+    }
+}
+
+#[test]
+fn bindings_gnome_keeps_other_shortcuts() {
+    // A gsettings that already has a shortcut of its own, and one from an
+    // earlier export
+    let bin = temp_path("gnome-bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let log = bin.join("log");
+    let gsettings = bin.join("gsettings");
+    std::fs::write(
+        &gsettings,
+        format!(
+            r#"#!/bin/sh
+if [ "$1" = get ]; then
+  echo "['/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/custom0/', '/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings/influencer7/']"
+else
+  printf '%s\n' "$*" >> {}
+fi
+"#,
+            log.display()
+        ),
+    )
+    .unwrap();
+    let chmod = Command::new("chmod").arg("+x").arg(&gsettings).status();
+    assert!(chmod.unwrap().success());
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/bindings");
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let status = Command::new("sh")
+        .arg(dir.join("gnome.sh"))
+        .env("PATH", path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let log = std::fs::read_to_string(&log).unwrap();
+    let list = log.lines().next().unwrap();
+    let dir = "/org/gnome/settings-daemon/plugins/media-keys/custom-keybindings";
+    assert_eq!(
+        list,
+        format!(
+            "set org.gnome.settings-daemon.plugins.media-keys custom-keybindings \
+             ['{dir}/custom0/', '{dir}/influencer1/', '{dir}/influencer2/', \
+             '{dir}/influencer3/', '{dir}/influencer4/', '{dir}/influencer5/']"
+        )
+    );
+    assert_eq!(log.lines().count(), 16);
+    std::fs::remove_dir_all(bin).unwrap();
+}
+
+#[test]
+fn script() {
+    let script = Script::new()
+        .respond("GetVersion", Some(json!({"obsVersion": "30.0.0"})))
+        .respond("SetCurrentProgramScene", None)
+        .respond("StartReplayBuffer", None)
+        .event(
+            Trigger::OnRequest("SetCurrentProgramScene".into()),
+            "CurrentProgramSceneChanged",
+            4,
+            Some(json!({"sceneName": "BRB"})),
+        );
+    let server = MockServer::start(script).unwrap();
+    let path = temp_path("script.rhai");
+    std::fs::write(
+        &path,
+        r#"
+print(request("GetVersion").obsVersion);
+let results = batch([
+    #{ requestType: "StartReplayBuffer" },
+    #{ requestType: "StopRecord" },
+]);
+print(results.map(|result| result.requestStatus.result));
+try {
+    request("StopRecord");
+} catch (error) {
+    print("caught");
+}
+let ticks = 0;
+let ticker = every(10, || { ticks += 1; });
+cancel(ticker);
+after(5000, || print("never"));
+on("CurrentProgram*", |event| {
+    print(event.eventData.sceneName);
+    exit(ARGS.len());
+});
+request("SetCurrentProgramScene", #{ sceneName: "BRB" });
+"#,
+    )
+    .unwrap();
+
+    let output = influencer(&server)
+        .arg("script")
+        .arg(&path)
+        .args(["a", "--b", "c"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "30.0.0\n[true, false]\ncaught\nBRB\n"
+    );
+    let received = server.received();
+    assert_eq!(received.len(), 4);
+    assert_eq!(received[3]["requestData"]["sceneName"], "BRB");
+
+    std::fs::write(&path, r#"after(50, || print("later"));"#).unwrap();
+    let output = influencer(&server)
+        .arg("script")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "later\n");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn script_interrupt() {
+    let server = MockServer::start(Script::new().hang("GetStats")).unwrap();
+    let path = temp_path("interrupt.rhai");
+    // Sends SIGINT, and waits a little for the script to stop
+    let interrupt = |mut child: Child| {
+        let kill = Command::new("kill")
+            .args(["-INT", &child.id().to_string()])
+            .status()
+            .unwrap();
+        assert!(kill.success());
+        let start = std::time::Instant::now();
+        while child.try_wait().unwrap().is_none() {
+            if start.elapsed() > Duration::from_secs(5) {
+                child.kill().unwrap();
+                panic!("The script didn't stop");
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        child.wait_with_output().unwrap()
+    };
+
+    // While waiting for a response, which try can't catch
+    std::fs::write(
+        &path,
+        r#"try { request("GetStats"); } catch { print("caught"); }"#,
+    )
+    .unwrap();
+    let child = influencer(&server)
+        .arg("script")
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    wait_for_received(&server, 1);
+    let output = interrupt(child);
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+
+    // While sleeping
+    std::fs::write(&path, r#"print("sleeping"); sleep(60000); print("never");"#).unwrap();
+    let mut child = influencer(&server)
+        .arg("script")
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(line, "sleeping\n");
+    let output = interrupt(child);
+    assert!(output.status.success());
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.is_empty());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn run_file() {
+    let script = Script::new()
+        .respond("GetSceneItemId", Some(json!({"sceneItemId": 7})))
+        .respond("SetSceneItemEnabled", None)
+        .respond(
+            "GetSceneItemEnabled",
+            Some(json!({"sceneItemEnabled": false})),
+        )
+        .event(
+            Trigger::OnRequest("SetSceneItemEnabled".into()),
+            "SceneItemEnableStateChanged",
+            128,
+            Some(json!({"sceneName": "Game", "sceneItemId": 7, "sceneItemEnabled": false})),
+        );
+    let server = MockServer::start(script).unwrap();
+    let path = temp_path("steps.toml");
+    let steps = r#"
+[[step]]
+request = "GetSceneItemId"
+data = { sceneName = "{scene}", sourceName = "Webcam" }
+capture = { id = ".sceneItemId" }
+
+[[step]]
+request = "SetSceneItemEnabled"
+data = { sceneName = "{scene}", sceneItemId = "{id}", sceneItemEnabled = false }
+
+[[step]]
+wait = "SceneItem*"
+where = [".sceneItemId == {id}"]
+timeout = 5
+
+[[step]]
+sleep = 0.01
+
+[[step]]
+name = "check"
+request = "GetSceneItemEnabled"
+data = { sceneName = "{scene}", sceneItemId = "{id}" }
+assert = [".sceneItemEnabled == false"]
+"#;
+    std::fs::write(&path, steps).unwrap();
+    let output = influencer(&server)
+        .arg("run-file")
+        .arg(&path)
+        .arg("scene=Game")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 4);
+    let received = server.received();
+    assert_eq!(received.len(), 3);
+    assert_eq!(
+        received[1]["requestData"],
+        json!({"sceneName": "Game", "sceneItemId": 7, "sceneItemEnabled": false})
+    );
+
+    std::fs::write(&path, steps.replace("== false", "== true")).unwrap();
+    let output = influencer(&server)
+        .arg("run-file")
+        .arg(&path)
+        .arg("scene=Game")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Step check"), "{stderr}");
+    assert!(
+        stderr.contains("Assertion failed: .sceneItemEnabled == true"),
+        "{stderr}"
+    );
+
+    std::fs::write(
+        &path,
+        "[[step]]\nwait = \"StreamStateChanged\"\ntimeout = 0.1\n",
+    )
+    .unwrap();
+    let output = influencer(&server)
+        .arg("run-file")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Timed out waiting for StreamStateChanged"));
+
+    std::fs::write(&path, "[[step]]\nsleep = 1\ncapture = { a = \".a\" }\n").unwrap();
+    let output = influencer(&server)
+        .arg("run-file")
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("capture can't be used in this step"));
+    let _ = std::fs::remove_file(&path);
+
+    let path = temp_path("steps.yaml");
+    let steps = r#"
+step:
+  - request: GetSceneItemId
+    data: { sceneName: "{scene}", sourceName: Webcam }
+    capture: { id: .sceneItemId }
+  - request: SetSceneItemEnabled
+    data: { sceneName: "{scene}", sceneItemId: "{id}", sceneItemEnabled: false }
+"#;
+    std::fs::write(&path, steps).unwrap();
+    let received = server.received().len();
+    let output = influencer(&server)
+        .arg("run-file")
+        .arg(&path)
+        .arg("scene=Game")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let received = &server.received()[received..];
+    assert_eq!(
+        received[1]["requestData"],
+        json!({"sceneName": "Game", "sceneItemId": 7, "sceneItemEnabled": false})
+    );
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn protocol_reference() {
+    // No server: the reference is built in
+    let run = |args: &[&str]| {
+        let output = influencer_unconfigured().args(args).output().unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        (output.status.success(), stdout)
+    };
+
+    let (success, requests) = run(&["list", "requests"]);
+    assert!(success);
+    assert!(requests.contains("SetInputVolume\tSets the volume setting of an input.\n"));
+    assert!(requests.contains("Sleep\t"));
+    let (_, scene_items) = run(&["list", "events", "--category", "scene items"]);
+    assert!(scene_items.starts_with("SceneItemCreated\t"));
+    assert!(
+        scene_items
+            .lines()
+            .all(|line| line.starts_with("SceneItem"))
+    );
+
+    let (success, volume) = run(&["describe", "SetInputVolume"]);
+    assert!(success);
+    assert!(volume.starts_with("SetInputVolume (request, inputs)\n"));
+    assert!(volume.contains("RPC version: 1\n"));
+    assert!(volume.contains("  inputVolumeDb   Number  optional  Volume setting in dB.\n"));
+    let (_, get_volume) = run(&["describe", "GetInputVolume"]);
+    assert!(
+        get_volume.contains("Response fields:\n  inputVolumeMul  Number  Volume setting in mul.\n")
+    );
+    let (_, meters) = run(&["describe", "inputvolumemeters"]);
+    assert!(meters.starts_with("InputVolumeMeters (event, inputs)\n"));
+    assert!(meters.contains("Subscription: InputVolumeMeters (1 << 16)\n"));
+
+    let (_, json) = run(&["-o", "json", "describe", "CurrentProgramSceneChanged"]);
+    let event: Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(event["eventSubscription"], "Scenes");
+    assert_eq!(event["dataFields"][0]["valueName"], "sceneName");
+
+    let (success, _) = run(&["describe", "NoSuchType"]);
+    assert!(!success);
+}
+
+/// A TLS-terminating proxy in front of `server`, with the certificate in
+/// tests/tls, like a reverse proxy in front of OBS. Returns its port.
+#[cfg(feature = "tls")]
+fn tls_proxy(server: &MockServer) -> u16 {
+    use rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject as _},
+    };
+    use std::{net::TcpListener, sync::Arc};
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/tls");
+    let certs = CertificateDer::pem_file_iter(dir.join("server.pem"))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let key = PrivateKeyDer::from_pem_file(dir.join("server.key")).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .unwrap();
+    let config = Arc::new(config);
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let upstream = server.addr();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let config = config.clone();
+            std::thread::spawn(move || {
+                let _ = forward_tls(config, stream, upstream);
+            });
+        }
+    });
+    port
+}
+
+#[cfg(feature = "tls")]
+fn forward_tls(
+    config: std::sync::Arc<rustls::ServerConfig>,
+    stream: std::net::TcpStream,
+    upstream: std::net::SocketAddr,
+) -> std::io::Result<()> {
+    use std::io::{ErrorKind, Read as _};
+    let timeout = Some(Duration::from_millis(10));
+    stream.set_read_timeout(timeout)?;
+    let mut upstream = std::net::TcpStream::connect(upstream)?;
+    upstream.set_read_timeout(timeout)?;
+    let connection = rustls::ServerConnection::new(config).map_err(std::io::Error::other)?;
+    let mut tls = rustls::StreamOwned::new(connection, stream);
+    let timed_out = |error: &std::io::Error| {
+        matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+    };
+    let mut buf = [0; 4096];
+    loop {
+        match tls.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => upstream.write_all(&buf[..n])?,
+            Err(error) if timed_out(&error) => {}
+            Err(error) => return Err(error),
+        }
+        match upstream.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                tls.write_all(&buf[..n])?;
+                tls.flush()?;
+            }
+            Err(error) if timed_out(&error) => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn tls() {
+    let script = Script::new().respond("GetVersion", None);
+    let server = MockServer::start(script).unwrap();
+    let port = tls_proxy(&server);
+    let ca_file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/tls/ca.pem");
+    let ca_file = ca_file.to_str().unwrap();
+    let url = format!("wss://localhost:{port}/obs?client=influencer");
+    let version = ["request", "GetVersion"];
+
+    let trusted = ["--host", &url, "--ca-file", ca_file];
+    run(influencer_unconfigured().args(trusted).args(version));
+    // The path survives, for path-routed reverse proxies
+    assert_eq!(server.paths(), ["/obs?client=influencer"]);
+    let port = port.to_string();
+    let by_address = ["--host", "127.0.0.1", "--port", &port, "--tls"];
+    run(influencer_unconfigured()
+        .args(by_address)
+        .args(["--ca-file", ca_file])
+        .args(version));
+    run(influencer_unconfigured()
+        .args(["--host", &url, "--insecure"])
+        .args(version));
+
+    let untrusted = influencer_unconfigured()
+        .args(["--host", &url])
+        .args(version)
+        .output()
+        .unwrap();
+    assert!(!untrusted.status.success());
+    assert_eq!(server.identified(), 3);
+
+    let plain = influencer(&server)
+        .args(["--ca-file", ca_file])
+        .args(version)
+        .output()
+        .unwrap();
+    assert!(!plain.status.success());
+    let stderr = String::from_utf8(plain.stderr).unwrap();
+    assert!(stderr.contains("--ca-file needs TLS"), "{stderr}");
+}
@@ -0,0 +1,524 @@
+//! A mock OBS WebSocket server, for testing OBS clients without OBS.
+//!
+//! The server performs the full obs-websocket handshake (including
+//! authentication, if a password is configured), answers requests from a
+//! table of fixtures, and emits scripted events.
+
+pub use influencer::server::DEFAULT_EVENT_SUBSCRIPTIONS;
+use influencer::{
+    message::{
+        self as m, IntoWsMessageJson as _, MessageData as _, WsMessageExt as _, close_code,
+        status_code,
+    },
+    server::ServerAuth,
+};
+
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind as IoErrorKind,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tungstenite::{
+    Error as WsError, Message as WsMessage, WebSocket,
+    handshake::server::Request as HandshakeRequest,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+const REQUEST_OP: i32 = m::Request::<'static, ()>::OP;
+const REQUEST_BATCH_OP: i32 = m::RequestBatch::<'static, ()>::OP;
+/// How often connections check for timed and emitted events.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The status part of a fixture's response.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Status {
+    pub result: bool,
+    pub code: i32,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            result: true,
+            code: status_code::SUCCESS,
+            comment: None,
+        }
+    }
+}
+
+/// What the server answers to requests of one type.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Fixture {
+    #[serde(default)]
+    pub request_status: Status,
+    #[serde(default)]
+    pub response_data: Option<Value>,
+}
+
+/// When a scripted event is emitted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Trigger {
+    /// Some time (given in milliseconds in JSON) after the client
+    /// has been identified.
+    #[serde(deserialize_with = "deserialize_millis")]
+    After(Duration),
+    /// Right after responding to a request of the given type.
+    OnRequest(String),
+}
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+}
+
+/// An event the server sends to every client that is subscribed to it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptedEvent {
+    #[serde(flatten)]
+    pub trigger: Trigger,
+    pub event_type: String,
+    pub event_intent: u32,
+    #[serde(default)]
+    pub event_data: Option<Value>,
+}
+
+/// Describes how the server behaves. Can be built up with its methods,
+/// or deserialized from JSON like
+/// `{"password": "p4ssw0rd", "responses": {"GetVersion": {"responseData": {...}}},
+/// "events": [{"after": 100, "eventType": "...", "eventIntent": 4}]}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Script {
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub responses: HashMap<String, Fixture>,
+    #[serde(default)]
+    pub events: Vec<ScriptedEvent>,
+    /// Request types that are received, but never answered, like requests
+    /// OBS got stuck on. Batches containing them aren't answered either.
+    #[serde(default)]
+    pub hang: HashSet<String>,
+    /// Never confirm a client's close frame, like an OBS that stopped
+    /// responding just as the client was done.
+    #[serde(default)]
+    pub ignore_close: bool,
+}
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Require clients to authenticate with this password.
+    pub fn password(mut self, password: impl Into<String>) -> Self {
+        self.password = Some(password.into());
+        self
+    }
+    /// Answer requests of this type successfully, with the given data.
+    pub fn respond(mut self, request_type: impl Into<String>, data: Option<Value>) -> Self {
+        let fixture = Fixture {
+            request_status: Status::default(),
+            response_data: data,
+        };
+        self.responses.insert(request_type.into(), fixture);
+        self
+    }
+    /// Answer requests of this type with a failure status.
+    pub fn fail(mut self, request_type: impl Into<String>, code: i32, comment: &str) -> Self {
+        let fixture = Fixture {
+            request_status: Status {
+                result: false,
+                code,
+                comment: Some(comment.to_owned()),
+            },
+            response_data: None,
+        };
+        self.responses.insert(request_type.into(), fixture);
+        self
+    }
+    /// Never answer requests of this type, see [`Script::hang`].
+    pub fn hang(mut self, request_type: impl Into<String>) -> Self {
+        self.hang.insert(request_type.into());
+        self
+    }
+    /// See [`Script::ignore_close`].
+    pub fn ignore_close(mut self) -> Self {
+        self.ignore_close = true;
+        self
+    }
+    /// Emit an event, after some time or in reaction to a request.
+    pub fn event(
+        mut self,
+        trigger: Trigger,
+        event_type: impl Into<String>,
+        event_intent: u32,
+        event_data: Option<Value>,
+    ) -> Self {
+        self.events.push(ScriptedEvent {
+            trigger,
+            event_type: event_type.into(),
+            event_intent,
+            event_data,
+        });
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    script: Script,
+    stopped: AtomicBool,
+    received: Mutex<Vec<Value>>,
+    paths: Mutex<Vec<String>>,
+    connections: Mutex<Vec<Sender<ScriptedEvent>>>,
+    identified: AtomicUsize,
+    closed: AtomicUsize,
+    frozen: AtomicBool,
+    /// Starts out as the script's, see [`MockServer::set_password`].
+    password: Mutex<Option<String>>,
+    /// Incremented to drop all current connections.
+    generation: AtomicUsize,
+}
+
+/// A running mock server, listening on a random local port.
+/// Stops accepting connections when dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    listener: Option<JoinHandle<()>>,
+}
+impl MockServer {
+    pub fn start(script: Script) -> std::io::Result<MockServer> {
+        Self::start_on(script, 0)
+    }
+    /// Like [`MockServer::start`], but on a given local port, e.g. one that
+    /// a client is already trying to reach.
+    pub fn start_on(script: Script, port: u16) -> std::io::Result<MockServer> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            password: Mutex::new(script.password.clone()),
+            script,
+            stopped: AtomicBool::new(false),
+            received: Mutex::new(Vec::new()),
+            paths: Mutex::new(Vec::new()),
+            connections: Mutex::new(Vec::new()),
+            identified: AtomicUsize::new(0),
+            closed: AtomicUsize::new(0),
+            frozen: AtomicBool::new(false),
+            generation: AtomicUsize::new(0),
+        });
+        let shared_ = shared.clone();
+        let listener = thread::spawn(move || listen(shared_, listener));
+        Ok(MockServer {
+            addr,
+            shared,
+            listener: Some(listener),
+        })
+    }
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    pub fn port(&self) -> u16 {
+        self.addr.port()
+    }
+    /// The `d` field of every request and request batch received so far,
+    /// from all connections.
+    pub fn received(&self) -> Vec<Value> {
+        self.shared.received.lock().unwrap().clone()
+    }
+    /// The request path (and query) of every WebSocket handshake so far.
+    pub fn paths(&self) -> Vec<String> {
+        self.shared.paths.lock().unwrap().clone()
+    }
+    /// The number of clients that have successfully identified so far.
+    pub fn identified(&self) -> usize {
+        self.shared.identified.load(Ordering::Relaxed)
+    }
+    /// The number of clients that have completed a closing handshake.
+    pub fn closed(&self) -> usize {
+        self.shared.closed.load(Ordering::Relaxed)
+    }
+    /// While frozen, identified connections neither read nor write, like
+    /// a hung OBS: requests go unanswered and pings aren't ponged.
+    pub fn set_frozen(&self, frozen: bool) {
+        self.shared.frozen.store(frozen, Ordering::Relaxed);
+    }
+    /// Changes the password required from new connections, like a user
+    /// changing it in OBS.
+    pub fn set_password(&self, password: Option<&str>) {
+        *self.shared.password.lock().unwrap() = password.map(str::to_owned);
+    }
+    /// Drops all current connections without a closing handshake, like a
+    /// crashed OBS. New connections are accepted as usual.
+    pub fn disconnect(&self) {
+        self.shared.generation.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Emits an event to all identified clients that are subscribed to it.
+    pub fn emit(&self, event_type: &str, event_intent: u32, event_data: Option<Value>) {
+        let event = ScriptedEvent {
+            trigger: Trigger::After(Duration::ZERO),
+            event_type: event_type.to_owned(),
+            event_intent,
+            event_data,
+        };
+        let mut connections = self.shared.connections.lock().unwrap();
+        connections.retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        // Wake up the listener so that it notices
+        let _ = TcpStream::connect(self.addr);
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+fn listen(shared: Arc<Shared>, listener: TcpListener) {
+    for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::Relaxed) {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (tx, rx) = mpsc::channel();
+        shared.connections.lock().unwrap().push(tx);
+        let shared = shared.clone();
+        thread::spawn(move || {
+            let _ = serve(&shared, stream, rx);
+        });
+    }
+}
+
+fn close_frame(code: u16, reason: &str) -> Option<CloseFrame> {
+    Some(CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.into(),
+    })
+}
+
+/// Handles one client connection until it is closed.
+fn serve(
+    shared: &Shared,
+    stream: TcpStream,
+    emitted: Receiver<ScriptedEvent>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let script = &shared.script;
+    let generation = shared.generation.load(Ordering::Relaxed);
+    // The error type is tungstenite's
+    #[allow(clippy::result_large_err)]
+    let mut ws = tungstenite::accept_hdr(stream, |request: &HandshakeRequest, response| {
+        let path = request.uri().path_and_query().map_or("/", |v| v.as_str());
+        shared.paths.lock().unwrap().push(path.to_owned());
+        Ok(response)
+    })
+    .map_err(|error| error.to_string())?;
+    let password = shared.password.lock().unwrap().clone();
+    let auth = ServerAuth::new(password.as_deref());
+    let Ok(identified) = auth.handshake(&mut ws) else {
+        // The client has been told why, if appropriate
+        return Ok(());
+    };
+    shared.identified.fetch_add(1, Ordering::Relaxed);
+    let mut event_subscriptions = identified.event_subscriptions;
+
+    let identified_at = Instant::now();
+    let mut timed: Vec<&ScriptedEvent> = script
+        .events
+        .iter()
+        .filter(|v| matches!(v.trigger, Trigger::After(_)))
+        .collect();
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    loop {
+        if shared.generation.load(Ordering::Relaxed) != generation {
+            return Ok(());
+        }
+        if shared.frozen.load(Ordering::Relaxed) {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        }
+        let mut due = Vec::new();
+        timed.retain(|event| match event.trigger {
+            Trigger::After(delay) if identified_at.elapsed() >= delay => {
+                due.push(*event);
+                false
+            }
+            _ => true,
+        });
+        for event in due.into_iter().cloned().chain(emitted.try_iter()) {
+            send_event(&mut ws, event_subscriptions, &event)?;
+        }
+        let message = match ws.read() {
+            Ok(message) => message,
+            Err(WsError::Io(error)) if error.kind() == IoErrorKind::WouldBlock => continue,
+            Err(WsError::ConnectionClosed) => {
+                shared.closed.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(error) => return Err(error.into()),
+        };
+        if message.is_close() && script.ignore_close {
+            // The confirmation would be sent by the next read
+            return wait_for_disconnect(ws.get_ref());
+        }
+        let WsMessage::Text(ref text) = message else {
+            continue;
+        };
+        let raw: m::Raw<Value> = serde_json::from_str(text)?;
+        let mut triggered = Vec::new();
+        match raw.op {
+            m::Reidentify::OP => {
+                let reidentify = message.obs_message_data::<m::Reidentify>()?;
+                event_subscriptions = reidentify
+                    .event_subscriptions
+                    .unwrap_or(event_subscriptions);
+                ws.send(
+                    m::Identified {
+                        negotiated_rpc_version: 1,
+                    }
+                    .into_ws_message_json()?,
+                )?;
+            }
+            op @ (REQUEST_OP | REQUEST_BATCH_OP) => {
+                shared.received.lock().unwrap().push(raw.d);
+                if op == REQUEST_OP {
+                    let request = message.obs_message_data::<m::Request<Value>>()?;
+                    let fixture = fixture(script, request.request_type);
+                    let response = m::Response {
+                        request_type: request.request_type,
+                        request_id: request.request_id,
+                        request_status: fixture.status(),
+                        response_data: fixture.response_data.as_ref(),
+                    };
+                    if !script.hang.contains(request.request_type) {
+                        ws.send(response.into_ws_message_json()?)?;
+                    }
+                    triggered.push(request.request_type);
+                } else {
+                    let batch = message.obs_message_data::<m::RequestBatchVec<Value>>()?;
+                    let mut fixtures = Vec::new();
+                    for item in batch.requests.iter() {
+                        let fixture = fixture(script, item.request_type);
+                        let failed = !fixture.request_status.result;
+                        fixtures.push((item, fixture));
+                        triggered.push(item.request_type);
+                        if failed && batch.halt_on_failure == Some(true) {
+                            break;
+                        }
+                    }
+                    let results: Vec<_> = fixtures
+                        .iter()
+                        .map(|(item, fixture)| m::response_batch::ResultsItem {
+                            request_type: item.request_type,
+                            request_id: item.request_id,
+                            request_status: fixture.status(),
+                            response_data: fixture.response_data.as_ref(),
+                        })
+                        .collect();
+                    let response = m::ResponseBatch {
+                        request_id: batch.request_id,
+                        results,
+                    };
+                    let hangs = batch
+                        .requests
+                        .iter()
+                        .any(|item| script.hang.contains(item.request_type));
+                    if !hangs {
+                        ws.send(response.into_ws_message_json()?)?;
+                    }
+                }
+            }
+            op => {
+                let reason = format!("Unknown OpCode {op}.");
+                ws.close(close_frame(close_code::UNKNOWN_OP_CODE, &reason))?;
+                return finish(ws);
+            }
+        }
+        for request_type in triggered {
+            for event in script.events.iter() {
+                if matches!(event.trigger, Trigger::OnRequest(ref v) if v == request_type) {
+                    send_event(&mut ws, event_subscriptions, event)?;
+                }
+            }
+        }
+    }
+}
+
+/// Waits for the client to go away, without reading any messages.
+fn wait_for_disconnect(stream: &TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match stream.peek(&mut [0]) {
+            Ok(0) => return Ok(()),
+            Ok(_) => thread::sleep(POLL_INTERVAL),
+            Err(error) if error.kind() == IoErrorKind::WouldBlock => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Waits for the client to acknowledge a close frame.
+fn finish(mut ws: WebSocket<TcpStream>) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match ws.read() {
+            Ok(_) => continue,
+            Err(WsError::ConnectionClosed) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+fn send_event(
+    ws: &mut WebSocket<TcpStream>,
+    event_subscriptions: u32,
+    event: &ScriptedEvent,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if event_subscriptions & event.event_intent == 0 {
+        return Ok(());
+    }
+    let event = m::Event {
+        event_type: &event.event_type,
+        event_intent: event.event_intent,
+        event_data: event.event_data.as_ref(),
+    };
+    ws.send(event.into_ws_message_json()?)?;
+    Ok(())
+}
+
+fn fixture(script: &Script, request_type: &str) -> Fixture {
+    match script.responses.get(request_type) {
+        Some(fixture) => fixture.clone(),
+        None => Fixture {
+            request_status: Status {
+                result: false,
+                code: status_code::UNKNOWN_REQUEST_TYPE,
+                comment: Some(format!("No fixture for request type {request_type}.")),
+            },
+            response_data: None,
+        },
+    }
+}
+
+impl Fixture {
+    fn status(&self) -> m::response::RequestStatus<'_> {
+        m::response::RequestStatus {
+            result: self.request_status.result,
+            code: self.request_status.code,
+            comment: self.request_status.comment.as_deref(),
+        }
+    }
+}
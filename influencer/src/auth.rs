@@ -2,7 +2,7 @@ use crate::message::{self as m, IntoWsMessageJson as _, WsMessageExt as _};
 
 use std::io::{ErrorKind as IoErrorKind, Read, Write};
 use thiserror::Error;
-use tungstenite::{Error as WsError, Message as WsMessage, WebSocket};
+use tungstenite::{Error as WsError, Message as WsMessage, WebSocket, protocol::frame::CloseFrame};
 
 #[derive(Debug)]
 enum State {
@@ -25,6 +25,15 @@ pub enum Error {
     /// or client violated the protocol.
     #[error("Unexpected message ({0})")]
     Decode(#[from] m::DecodeError),
+    /// The other side sent a close frame, optionally with a code and reason.
+    #[error("Connection closed by peer ({})", display_close_frame(.0))]
+    Closed(Option<CloseFrame>),
+}
+pub(crate) fn display_close_frame(frame: &Option<CloseFrame>) -> String {
+    match frame {
+        Some(frame) => frame.to_string(),
+        None => "no close frame".to_string(),
+    }
 }
 impl From<WsError> for Error {
     fn from(value: WsError) -> Self {
@@ -32,6 +41,23 @@ impl From<WsError> for Error {
     }
 }
 
+/// Computes the `authentication` value of an `Identify` message
+/// from the password and the server's salt and challenge.
+pub fn authentication_string(password: &str, salt: &str, challenge: &str) -> String {
+    use base64ct::Encoding;
+    use sha2::Digest;
+    let secret = sha2::Sha256::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .finalize();
+    let secret = base64ct::Base64::encode_string(&secret);
+    let auth_string = sha2::Sha256::new()
+        .chain_update(secret)
+        .chain_update(challenge)
+        .finalize();
+    base64ct::Base64::encode_string(&auth_string)
+}
+
 /// The result of attempting to drive an [`AuthMachine`].
 pub enum DriveResult<'a, Stream> {
     /// The stream is ready to be used to communicate with OBS.
@@ -65,6 +91,31 @@ pub trait MessageStream {
     fn read(&mut self) -> Result<WsMessage, WsError>;
     fn write(&mut self, message: WsMessage) -> Result<(), WsError>;
     fn flush(&mut self) -> Result<(), WsError>;
+    /// Reads the next text or binary message, skipping ping, pong and raw
+    /// frame messages. A close message is turned into [`Error::Closed`].
+    fn read_data(&mut self) -> Result<WsMessage, Error> {
+        loop {
+            match self.read()? {
+                WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => continue,
+                WsMessage::Close(frame) => return Err(Error::Closed(frame)),
+                message => return Ok(message),
+            }
+        }
+    }
+    /// Performs the WebSocket closing handshake: sends a close frame, then
+    /// discards incoming messages until the other side confirms.
+    /// Safe to call again after an I/O WouldBlock error.
+    fn close_handshake(&mut self, frame: Option<CloseFrame>) -> Result<(), WsError> {
+        self.write(WsMessage::Close(frame))?;
+        self.flush()?;
+        loop {
+            match self.read() {
+                Ok(_) => continue,
+                Err(WsError::ConnectionClosed) => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        }
+    }
 }
 impl<Stream: Read + Write> MessageStream for WebSocket<Stream> {
     fn read(&mut self) -> Result<WsMessage, WsError> {
@@ -77,6 +128,17 @@ impl<Stream: Read + Write> MessageStream for WebSocket<Stream> {
         self.flush()
     }
 }
+impl<Stream: MessageStream + ?Sized> MessageStream for &mut Stream {
+    fn read(&mut self) -> Result<WsMessage, WsError> {
+        (**self).read()
+    }
+    fn write(&mut self, message: WsMessage) -> Result<(), WsError> {
+        (**self).write(message)
+    }
+    fn flush(&mut self) -> Result<(), WsError> {
+        (**self).flush()
+    }
+}
 
 /// An OBS authentication state machine.
 #[derive(Debug)]
@@ -153,7 +215,7 @@ impl<'a, Stream: MessageStream> AuthMachine<'a, Stream> {
         }
         match self.state {
             State::Connected => {
-                let hello = self.stream.read()?;
+                let hello = self.stream.read_data()?;
                 let hello = hello.obs_message_data::<m::Hello>()?;
                 let auth = hello
                     .authentication
@@ -161,22 +223,9 @@ impl<'a, Stream: MessageStream> AuthMachine<'a, Stream> {
                 self.state = State::GotHello(auth);
             }
             State::GotHello(ref auth_params) => {
-                use base64ct::Encoding;
-                use sha2::Digest;
-                let mut authentication: Option<String> = None;
-                if let Some((challenge, salt)) = auth_params {
-                    let auth_string = sha2::Sha256::new()
-                        .chain_update(self.password.unwrap_or(""))
-                        .chain_update(salt)
-                        .finalize();
-                    let auth_string = base64ct::Base64::encode_string(&auth_string);
-                    let auth_string = sha2::Sha256::new()
-                        .chain_update(auth_string)
-                        .chain_update(challenge)
-                        .finalize();
-                    let auth_string = base64ct::Base64::encode_string(&auth_string);
-                    authentication = Some(auth_string);
-                }
+                let authentication = auth_params.as_ref().map(|(challenge, salt)| {
+                    authentication_string(self.password.unwrap_or(""), salt, challenge)
+                });
                 let data = m::Identify {
                     rpc_version: 1,
                     authentication: authentication.as_deref(),
@@ -190,7 +239,7 @@ impl<'a, Stream: MessageStream> AuthMachine<'a, Stream> {
                 self.needs_flush = true;
             }
             State::SentIdentify => {
-                let identified = self.stream.read()?;
+                let identified = self.stream.read_data()?;
                 let identified = identified.obs_message_data::<m::Identified>()?;
                 self.state = State::Ready(identified.negotiated_rpc_version);
             }
@@ -0,0 +1,512 @@
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::TlsOptions;
+
+use crate::{
+    auth::{self, AuthMachine, MessageStream},
+    message::{self as m, IntoWsMessageJson as _, close_code},
+    record::Recorder,
+};
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{ErrorKind as IoErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tungstenite::{
+    Bytes, Error as WsError, HandshakeError, Message as WsMessage, WebSocket,
+    protocol::frame::CloseFrame,
+};
+
+/// How long to wait before the first reconnection attempt.
+const RECONNECT_DELAY_MIN: Duration = Duration::from_millis(500);
+/// Reconnection delays double after every failed attempt, up to this limit.
+const RECONNECT_DELAY_MAX: Duration = Duration::from_secs(30);
+/// How long to wait for the server to confirm a close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+/// The default for [`Config::handshake_timeout`].
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Errors that might occur while connecting or talking to OBS.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// Establishing or configuring the TCP connection failed.
+    #[error("Connection failed ({0})")]
+    Io(#[from] std::io::Error),
+    /// An error coming from the underlying WebSocket connection.
+    #[error("Underlying WebSocket error ({0})")]
+    WebSocket(Box<WsError>),
+    /// OBS authentication failed.
+    #[error("Authentication failed ({0})")]
+    Auth(#[from] auth::Error),
+    /// The other side sent a close frame, optionally with a code and reason.
+    #[error("Connection closed by peer ({})", auth::display_close_frame(.0))]
+    Closed(Option<CloseFrame>),
+    /// A message could not be serialized.
+    #[error("JSON serialize failed ({0})")]
+    Json(#[from] serde_json::Error),
+    /// A keepalive ping went unanswered for too long.
+    #[error("No response to keepalive ping within {0:?}")]
+    KeepaliveTimeout(Duration),
+    /// Setting up TLS failed.
+    #[cfg(feature = "tls")]
+    #[error("TLS error ({0})")]
+    Tls(#[from] rustls::Error),
+}
+impl From<WsError> for Error {
+    fn from(value: WsError) -> Self {
+        Error::WebSocket(Box::new(value))
+    }
+}
+impl Error {
+    /// Whether the error means that the connection is gone and
+    /// reconnecting might help.
+    pub fn is_disconnect(&self) -> bool {
+        match self {
+            Error::Io(_) | Error::Closed(_) | Error::KeepaliveTimeout(_) => true,
+            Error::WebSocket(error) => matches!(
+                error.as_ref(),
+                WsError::ConnectionClosed
+                    | WsError::AlreadyClosed
+                    | WsError::Io(_)
+                    | WsError::Protocol(_)
+            ),
+            Error::Auth(auth::Error::WebSocket(_)) => true,
+            // Unless OBS refused us, e.g. for a wrong password
+            Error::Auth(auth::Error::Closed(frame)) => !is_refusal(frame),
+            Error::Auth(_) | Error::Json(_) => false,
+            #[cfg(feature = "tls")]
+            Error::Tls(_) => false,
+        }
+    }
+}
+
+/// Whether OBS closed the connection with one of its own close codes, other
+/// than for an unknown reason. Trying again won't change its mind.
+fn is_refusal(frame: &Option<CloseFrame>) -> bool {
+    frame.as_ref().is_some_and(|frame| {
+        let code = u16::from(frame.code);
+        (close_code::MESSAGE_DECODE_ERROR..=close_code::UNSUPPORTED_FEATURE).contains(&code)
+    })
+}
+
+/// Keepalive settings. While waiting for messages, a ping is sent every
+/// `interval`, and the connection is considered dead if nothing at all is
+/// received within `timeout` of sending it.
+#[derive(Debug, Clone, Copy)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Everything needed to (re)establish a connection to OBS.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// A host name or IP address, without brackets around IPv6 addresses.
+    pub host: String,
+    pub port: u16,
+    /// The path (and query) of the WebSocket URL, like `/obs` for OBS
+    /// behind a path-routed reverse proxy.
+    pub path: String,
+    pub password: Option<String>,
+    pub event_subscriptions: Option<u32>,
+    /// Connect using `wss://` if set.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsOptions>,
+    /// Read timeout of the underlying socket. See [`Client::try_read`].
+    pub read_timeout: Option<Duration>,
+    /// How long each of connecting, the WebSocket handshake and reading each
+    /// authentication message may take, so that a silent server can't hang
+    /// [`Client::connect`] or a reconnect.
+    pub handshake_timeout: Duration,
+    /// Disabled if `None`.
+    pub keepalive: Option<Keepalive>,
+    /// Reconnect (and re-authenticate) transparently when the connection
+    /// is lost, instead of returning an error. Requests that were in flight
+    /// at that moment will never receive a response. Attempts are repeated
+    /// while the connection fails, but any other error, like a wrong
+    /// password, is returned.
+    pub auto_reconnect: bool,
+    /// Gives up reconnecting after this many failed attempts in a row,
+    /// returning the last error. Unlimited if `None`.
+    pub reconnect_attempts: Option<u32>,
+    /// Perform the closing handshake (see [`Client::close`]) when the
+    /// [`Client`] is dropped without having been closed explicitly.
+    pub close_on_drop: bool,
+    /// Append all traffic, including authentication, to this file, one
+    /// session per connection. See [`crate::record`].
+    pub record: Option<PathBuf>,
+}
+impl Config {
+    /// The WebSocket URL to connect to.
+    pub fn url(&self) -> String {
+        #[cfg(feature = "tls")]
+        let scheme = if self.tls.is_some() { "wss" } else { "ws" };
+        #[cfg(not(feature = "tls"))]
+        let scheme = "ws";
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        format!("{scheme}://{host}:{}{}", self.port, self.path)
+    }
+    /// Creates a [`Config`] for the path `/`, without a password, event subscriptions,
+    /// keepalive, auto-reconnect or close-on-drop, and with the default
+    /// [`HANDSHAKE_TIMEOUT`].
+    pub fn new(host: impl Into<String>, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+            path: "/".to_owned(),
+            password: None,
+            event_subscriptions: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            read_timeout: None,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            keepalive: None,
+            auto_reconnect: false,
+            reconnect_attempts: None,
+            close_on_drop: false,
+            record: None,
+        }
+    }
+}
+
+/// The transport underneath a [`Client`]'s WebSocket.
+#[derive(Debug)]
+pub enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tls::TlsStream>),
+}
+impl Stream {
+    /// The underlying TCP stream.
+    pub fn get_ref(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+}
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A [`Client`]'s WebSocket, possibly recording.
+#[derive(Debug)]
+enum Ws {
+    Plain(WebSocket<Stream>),
+    Recorded(Recorder<WebSocket<Stream>, File>),
+}
+impl Ws {
+    fn socket(&self) -> &WebSocket<Stream> {
+        match self {
+            Ws::Plain(ws) => ws,
+            Ws::Recorded(recorder) => recorder.get_ref(),
+        }
+    }
+    fn socket_mut(&mut self) -> &mut WebSocket<Stream> {
+        match self {
+            Ws::Plain(ws) => ws,
+            Ws::Recorded(recorder) => recorder.get_mut(),
+        }
+    }
+    fn send(&mut self, message: WsMessage) -> Result<(), Error> {
+        self.write(message)?;
+        Ok(self.flush()?)
+    }
+}
+impl MessageStream for Ws {
+    fn read(&mut self) -> Result<WsMessage, WsError> {
+        match self {
+            Ws::Plain(ws) => ws.read(),
+            Ws::Recorded(recorder) => recorder.read(),
+        }
+    }
+    fn write(&mut self, message: WsMessage) -> Result<(), WsError> {
+        match self {
+            Ws::Plain(ws) => ws.write(message),
+            Ws::Recorded(recorder) => recorder.write(message),
+        }
+    }
+    fn flush(&mut self) -> Result<(), WsError> {
+        match self {
+            Ws::Plain(ws) => ws.flush(),
+            Ws::Recorded(recorder) => recorder.flush(),
+        }
+    }
+}
+
+/// A blocking, authenticated connection to OBS.
+#[derive(Debug)]
+pub struct Client {
+    config: Config,
+    ws: Ws,
+    /// Shared by all connections, see [`Config::record`].
+    record: Option<File>,
+    rpc_version: u32,
+    last_ping: Instant,
+    pong_deadline: Option<Instant>,
+    closed: bool,
+    reconnects: u64,
+}
+impl Client {
+    /// Connects and authenticates.
+    pub fn connect(config: Config) -> Result<Client, Error> {
+        let record = match config.record {
+            Some(ref path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+        let (ws, rpc_version) = open(&config, record.as_ref())?;
+        Ok(Client {
+            config,
+            ws,
+            record,
+            rpc_version,
+            last_ping: Instant::now(),
+            pong_deadline: None,
+            closed: false,
+            reconnects: 0,
+        })
+    }
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// The RPC version negotiated with the server.
+    pub fn rpc_version(&self) -> u32 {
+        self.rpc_version
+    }
+    /// How many times the connection has been re-established, see
+    /// [`Config::auto_reconnect`]. Requests sent before a reconnect will
+    /// never be answered.
+    pub fn reconnects(&self) -> u64 {
+        self.reconnects
+    }
+    /// Messages read or written through the returned reference are not
+    /// recorded.
+    pub fn get_mut(&mut self) -> &mut WebSocket<Stream> {
+        self.ws.socket_mut()
+    }
+    /// Writes and flushes a message.
+    pub fn send(&mut self, message: WsMessage) -> Result<(), Error> {
+        match self.ws.send(message.clone()) {
+            Err(error) if self.should_reconnect(&error) => {
+                self.reconnect()?;
+                Ok(self.ws.send(message)?)
+            }
+            res => res,
+        }
+    }
+    /// Changes the event subscriptions of the session. They are also used
+    /// for future reconnects. OBS answers with an `Identified` message.
+    pub fn reidentify(&mut self, event_subscriptions: Option<u32>) -> Result<(), Error> {
+        self.config.event_subscriptions = event_subscriptions;
+        self.send(
+            m::Reidentify {
+                event_subscriptions,
+            }
+            .into_ws_message_json()?,
+        )
+    }
+    /// Blocks until a text or binary message is received.
+    /// Control frames are handled internally.
+    pub fn read(&mut self) -> Result<WsMessage, Error> {
+        loop {
+            if let Some(message) = self.try_read()? {
+                break Ok(message);
+            }
+        }
+    }
+    /// Like [`Client::read`], but returns `Ok(None)` whenever the read timeout
+    /// of the underlying socket expires without a message being received.
+    /// The timeout is [`Config::read_timeout`], shortened as needed to
+    /// keep up with keepalive.
+    pub fn try_read(&mut self) -> Result<Option<WsMessage>, Error> {
+        match self.try_read_internal() {
+            Err(error) if self.should_reconnect(&error) => {
+                self.reconnect()?;
+                Ok(None)
+            }
+            res => res,
+        }
+    }
+    fn try_read_internal(&mut self) -> Result<Option<WsMessage>, Error> {
+        self.keepalive_tick()?;
+        let message = match self.ws.read() {
+            Ok(message) => message,
+            Err(WsError::Io(error))
+                if matches!(
+                    error.kind(),
+                    IoErrorKind::WouldBlock | IoErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(None);
+            }
+            Err(error) => return Err(error.into()),
+        };
+        // Anything coming in proves that the connection is alive
+        self.pong_deadline = None;
+        match message {
+            WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Frame(_) => Ok(None),
+            WsMessage::Close(frame) => Err(Error::Closed(frame)),
+            message => Ok(Some(message)),
+        }
+    }
+    fn keepalive_tick(&mut self) -> Result<(), Error> {
+        let Some(keepalive) = self.config.keepalive else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        match self.pong_deadline {
+            Some(deadline) if now >= deadline => Err(Error::KeepaliveTimeout(keepalive.timeout)),
+            Some(_) => Ok(()),
+            None if now.duration_since(self.last_ping) >= keepalive.interval => {
+                self.ws.send(WsMessage::Ping(Bytes::new()))?;
+                self.last_ping = now;
+                self.pong_deadline = Some(now + keepalive.timeout);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+    /// Performs the WebSocket closing handshake, so that OBS sees an orderly
+    /// disconnect. Waits a short while for the server to confirm.
+    /// The client can not be used anymore afterwards.
+    pub fn close(&mut self, frame: Option<CloseFrame>) -> Result<(), Error> {
+        self.closed = true;
+        self.ws
+            .socket()
+            .get_ref()
+            .get_ref()
+            .set_read_timeout(Some(CLOSE_TIMEOUT))?;
+        Ok(self.ws.close_handshake(frame)?)
+    }
+    fn should_reconnect(&self, error: &Error) -> bool {
+        self.config.auto_reconnect && !self.closed && error.is_disconnect()
+    }
+    /// Retries until a new connection is established, an attempt fails for
+    /// a reason other than the connection, or
+    /// [`Config::reconnect_attempts`] have failed.
+    fn reconnect(&mut self) -> Result<(), Error> {
+        let mut delay = RECONNECT_DELAY_MIN;
+        let mut attempts = 0;
+        loop {
+            thread::sleep(delay);
+            attempts += 1;
+            match open(&self.config, self.record.as_ref()) {
+                Ok((ws, rpc_version)) => {
+                    self.ws = ws;
+                    self.rpc_version = rpc_version;
+                    self.last_ping = Instant::now();
+                    self.pong_deadline = None;
+                    self.reconnects += 1;
+                    return Ok(());
+                }
+                Err(error)
+                    if !error.is_disconnect()
+                        || self
+                            .config
+                            .reconnect_attempts
+                            .is_some_and(|max| attempts >= max) =>
+                {
+                    return Err(error);
+                }
+                Err(_) => delay = (delay * 2).min(RECONNECT_DELAY_MAX),
+            }
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.config.close_on_drop && !self.closed {
+            let _ = self.close(None);
+        }
+    }
+}
+
+fn open(config: &Config, record: Option<&File>) -> Result<(Ws, u32), Error> {
+    let Config { host, port, .. } = config;
+    let tcp_stream = connect_tcp(host, *port, config.handshake_timeout)?;
+    tcp_stream.set_read_timeout(Some(config.handshake_timeout))?;
+    tcp_stream.set_write_timeout(Some(config.handshake_timeout))?;
+    #[cfg(feature = "tls")]
+    let stream = match config.tls {
+        Some(ref options) => Stream::Tls(Box::new(tls::wrap(options, host, tcp_stream)?)),
+        None => Stream::Plain(tcp_stream),
+    };
+    #[cfg(not(feature = "tls"))]
+    let stream = Stream::Plain(tcp_stream);
+    let (ws, _res) =
+        tungstenite::client::client(config.url(), stream).map_err(|error| match error {
+            HandshakeError::Failure(error) => Error::from(error),
+            HandshakeError::Interrupted(_) => Error::Io(IoErrorKind::WouldBlock.into()),
+        })?;
+    let ws = match record {
+        Some(file) => Ws::Recorded(Recorder::new(ws, file.try_clone()?)),
+        None => Ws::Plain(ws),
+    };
+    let auth = AuthMachine::new(ws, config.password.as_deref(), config.event_subscriptions);
+    let (ws, rpc_version) = auth.drive().ready()?;
+    // Wake up often enough to send pings and notice missed deadlines
+    let keepalive_timeout = config.keepalive.map(|v| v.interval.min(v.timeout));
+    let timeout = match (config.read_timeout, keepalive_timeout) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+    let tcp_stream = ws.socket().get_ref().get_ref();
+    tcp_stream.set_read_timeout(timeout)?;
+    tcp_stream.set_write_timeout(None)?;
+    Ok((ws, rpc_version))
+}
+
+/// Connects to the first address of `host` that accepts within `timeout`.
+fn connect_tcp(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let mut last_error = None;
+    for addr in (host, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(error) => last_error = Some(error),
+        }
+    }
+    Err(last_error
+        .unwrap_or_else(|| IoErrorKind::NotFound.into())
+        .into())
+}
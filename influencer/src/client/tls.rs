@@ -0,0 +1,111 @@
+use super::Error;
+
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    StreamOwned,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject as _},
+};
+use std::{
+    io::{Error as IoError, ErrorKind as IoErrorKind},
+    net::TcpStream,
+    path::PathBuf,
+    sync::Arc,
+};
+
+/// TLS settings for `wss://` connections.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// A PEM bundle of trusted root certificates, used instead of the
+    /// built-in web PKI roots.
+    pub ca_file: Option<PathBuf>,
+    /// Accept any server certificate. Defeats the purpose of TLS against
+    /// active attackers, so only use this for testing.
+    pub insecure_skip_verify: bool,
+}
+
+pub(super) type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Wraps the stream in a TLS client connection. The TLS handshake itself
+/// happens lazily, on first read or write.
+pub(super) fn wrap(
+    options: &TlsOptions,
+    host: &str,
+    stream: TcpStream,
+) -> Result<TlsStream, Error> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let config = if options.insecure_skip_verify {
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoVerification(provider)))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        match options.ca_file {
+            Some(ref path) => {
+                for cert in CertificateDer::pem_file_iter(path).map_err(invalid_data)? {
+                    roots.add(cert.map_err(invalid_data)?)?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    let server_name = ServerName::try_from(host.to_owned())
+        .map_err(|error| IoError::new(IoErrorKind::InvalidInput, error))?;
+    let connection = ClientConnection::new(Arc::new(config), server_name)?;
+    Ok(StreamOwned::new(connection, stream))
+}
+
+fn invalid_data(error: rustls::pki_types::pem::Error) -> IoError {
+    IoError::new(IoErrorKind::InvalidData, error)
+}
+
+/// Accepts any certificate, but still checks handshake signatures
+/// so that the connection is at least well-formed.
+#[derive(Debug)]
+struct NoVerification(Arc<CryptoProvider>);
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
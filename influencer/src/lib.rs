@@ -1,4 +1,13 @@
 /// A state machine for driving OBS WebSocket authentication.
 pub mod auth;
+/// A blocking OBS WebSocket client with keepalive and auto-reconnect.
+pub mod client;
 /// Types and utilities for parsing and creating OBS WebSocket messages.
 pub mod message;
+/// Recording OBS WebSocket traffic to JSON lines, and replaying it.
+pub mod record;
+/// The server side of OBS WebSocket authentication, for proxies and mocks.
+pub mod server;
+/// In-memory [`auth::MessageStream`]s for testing code without a network.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -2,6 +2,91 @@ use serde::{Deserialize, Serialize, de};
 use std::marker::PhantomData;
 use tungstenite::Message as WsMessage;
 
+/// WebSocket close codes used by obs-websocket.
+pub mod close_code {
+    pub const UNKNOWN_REASON: u16 = 4000;
+    pub const MESSAGE_DECODE_ERROR: u16 = 4002;
+    pub const MISSING_DATA_FIELD: u16 = 4003;
+    pub const INVALID_DATA_FIELD_TYPE: u16 = 4004;
+    pub const INVALID_DATA_FIELD_VALUE: u16 = 4005;
+    pub const UNKNOWN_OP_CODE: u16 = 4006;
+    pub const NOT_IDENTIFIED: u16 = 4007;
+    pub const ALREADY_IDENTIFIED: u16 = 4008;
+    pub const AUTHENTICATION_FAILED: u16 = 4009;
+    pub const UNSUPPORTED_RPC_VERSION: u16 = 4010;
+    pub const SESSION_INVALIDATED: u16 = 4011;
+    pub const UNSUPPORTED_FEATURE: u16 = 4012;
+}
+
+/// `requestStatus` codes used by obs-websocket.
+pub mod status_code {
+    pub const SUCCESS: i32 = 100;
+    pub const MISSING_REQUEST_TYPE: i32 = 203;
+    pub const UNKNOWN_REQUEST_TYPE: i32 = 204;
+    pub const GENERIC_ERROR: i32 = 205;
+    pub const UNSUPPORTED_REQUEST_BATCH_EXECUTION_TYPE: i32 = 206;
+    pub const NOT_READY: i32 = 207;
+    pub const MISSING_REQUEST_FIELD: i32 = 300;
+    pub const MISSING_REQUEST_DATA: i32 = 301;
+    pub const INVALID_REQUEST_FIELD: i32 = 400;
+    pub const INVALID_REQUEST_FIELD_TYPE: i32 = 401;
+    pub const REQUEST_FIELD_OUT_OF_RANGE: i32 = 402;
+    pub const REQUEST_FIELD_EMPTY: i32 = 403;
+    pub const TOO_MANY_REQUEST_FIELDS: i32 = 404;
+    pub const OUTPUT_RUNNING: i32 = 500;
+    pub const OUTPUT_NOT_RUNNING: i32 = 501;
+    pub const OUTPUT_PAUSED: i32 = 502;
+    pub const OUTPUT_NOT_PAUSED: i32 = 503;
+    pub const OUTPUT_DISABLED: i32 = 504;
+    pub const STUDIO_MODE_ACTIVE: i32 = 505;
+    pub const STUDIO_MODE_NOT_ACTIVE: i32 = 506;
+    pub const RESOURCE_NOT_FOUND: i32 = 600;
+    pub const RESOURCE_ALREADY_EXISTS: i32 = 601;
+    pub const INVALID_RESOURCE_TYPE: i32 = 602;
+    pub const NOT_ENOUGH_RESOURCES: i32 = 603;
+    pub const INVALID_RESOURCE_STATE: i32 = 604;
+    pub const INVALID_INPUT_KIND: i32 = 605;
+    pub const RESOURCE_NOT_CONFIGURABLE: i32 = 606;
+    pub const INVALID_FILTER_KIND: i32 = 607;
+    pub const RESOURCE_CREATION_FAILED: i32 = 700;
+    pub const RESOURCE_ACTION_FAILED: i32 = 701;
+    pub const REQUEST_PROCESSING_FAILED: i32 = 702;
+    pub const CANNOT_ACT: i32 = 703;
+}
+
+/// `eventSubscriptions` bits used by obs-websocket.
+pub mod event_subscription {
+    pub const NONE: u32 = 0;
+    pub const GENERAL: u32 = 1 << 0;
+    pub const CONFIG: u32 = 1 << 1;
+    pub const SCENES: u32 = 1 << 2;
+    pub const INPUTS: u32 = 1 << 3;
+    pub const TRANSITIONS: u32 = 1 << 4;
+    pub const FILTERS: u32 = 1 << 5;
+    pub const OUTPUTS: u32 = 1 << 6;
+    pub const SCENE_ITEMS: u32 = 1 << 7;
+    pub const MEDIA_INPUTS: u32 = 1 << 8;
+    pub const VENDORS: u32 = 1 << 9;
+    pub const UI: u32 = 1 << 10;
+    /// Everything but the high-volume events below. Used if a client
+    /// doesn't ask for anything else.
+    pub const ALL: u32 = GENERAL
+        | CONFIG
+        | SCENES
+        | INPUTS
+        | TRANSITIONS
+        | FILTERS
+        | OUTPUTS
+        | SCENE_ITEMS
+        | MEDIA_INPUTS
+        | VENDORS
+        | UI;
+    pub const INPUT_VOLUME_METERS: u32 = 1 << 16;
+    pub const INPUT_ACTIVE_STATE_CHANGED: u32 = 1 << 17;
+    pub const INPUT_SHOW_STATE_CHANGED: u32 = 1 << 18;
+    pub const SCENE_ITEM_TRANSFORM_CHANGED: u32 = 1 << 19;
+}
+
 trait KBoolExt {
     fn k_ok_or_else<E, F: FnOnce() -> E>(self, f: F) -> Result<(), E>;
 }
@@ -43,6 +128,7 @@ impl<'de, T: Deserialize<'de> + MessageDataInfo> FromWsMessageJson<'de> for T {
 pub trait WsMessageExt {
     fn obs_message_data<'a, T: FromWsMessageJson<'a>>(&'a self) -> Result<T, DecodeError>;
     fn any_obs_server_message<'a>(&'a self) -> Result<ServerMessage<'a>, DecodeError>;
+    fn any_obs_client_message<'a>(&'a self) -> Result<ClientMessage<'a>, DecodeError>;
 }
 impl WsMessageExt for WsMessage {
     fn obs_message_data<'a, T: FromWsMessageJson<'a>>(&'a self) -> Result<T, DecodeError> {
@@ -54,6 +140,12 @@ impl WsMessageExt for WsMessage {
             _ => Err(DecodeError::NotText),
         }
     }
+    fn any_obs_client_message<'a>(&'a self) -> Result<ClientMessage<'a>, DecodeError> {
+        match self {
+            WsMessage::Text(text) => Ok(ClientMessage::from_json_str(text.as_str())?),
+            _ => Err(DecodeError::NotText),
+        }
+    }
 }
 pub trait MessageDataFull: MessageData {
     fn into_raw_message(self) -> Raw<Self> {
@@ -117,6 +209,10 @@ pub mod hello {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Hello<'a> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub obs_web_socket_version: Option<&'a str>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_version: Option<u32>,
     #[serde(borrow, skip_serializing_if = "Option::is_none")]
     pub authentication: Option<hello::Authentication<'a>>,
 }
@@ -188,6 +284,23 @@ impl<'a, T> Event<'a, T> {
 pub type AnyEvent<'a> = Event<'a, serde_json::Value>;
 impl_message_data_full!(impl<T> Event<'_, T>, 5);
 
+pub mod request {
+    use super::*;
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct InfoPart<'a> {
+        pub request_type: &'a str,
+        pub request_id: &'a str,
+    }
+    impl_message_data_info!(InfoPart<'_>, 6);
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DataPart<T> {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub request_data: Option<T>,
+    }
+    impl_message_data!(impl<T> DataPart<T>, 6);
+}
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Request<'a, T> {
@@ -265,6 +378,23 @@ pub mod request_batch {
         #[serde(skip_serializing_if = "Option::is_none")]
         pub request_data: Option<T>,
     }
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct InfoPart<'a> {
+        pub request_id: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub halt_on_failure: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub execution_type: Option<i32>,
+    }
+    impl_message_data_info!(InfoPart<'_>, 8);
+    #[derive(Debug, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RequestsPart<T> {
+        pub requests: T,
+    }
+    impl_message_data!(impl<T> RequestsPart<T>, 8);
+    pub type RequestsPartVec<'a, T> = RequestsPart<Vec<RequestsItem<'a, T>>>;
 }
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -413,6 +543,55 @@ where
     }
 }
 
+#[derive(Debug)]
+pub enum ClientMessage<'a> {
+    Identify(Identify<'a>),
+    Reidentify(Reidentify),
+    Request(request::InfoPart<'a>),
+    RequestBatch(request_batch::InfoPart<'a>),
+}
+impl<'a> ClientMessage<'a> {
+    pub fn from_json_str(json: &'a str) -> Result<ClientMessage<'a>, serde_json::Error> {
+        let op_part: raw::OpPart = serde_json::from_str(json)?;
+        let mut de = serde_json::Deserializer::from_str(json);
+        extract_client_message_data_auto(&mut de, op_part.op)
+    }
+    pub fn opcode(&self) -> i32 {
+        match self {
+            ClientMessage::Identify(_) => Identify::OP,
+            ClientMessage::Reidentify(_) => Reidentify::OP,
+            ClientMessage::Request(_) => request::InfoPart::OP,
+            ClientMessage::RequestBatch(_) => request_batch::InfoPart::OP,
+        }
+    }
+}
+
+pub fn extract_client_message_data_auto<'de, D>(
+    deserializer: D,
+    op: i32,
+) -> Result<ClientMessage<'de>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    macro_rules! match_op {
+        ($variant:ident,$data_type:path) => {
+            ClientMessage::$variant(
+                deserializer.deserialize_map(MessageDataVisitor::<$data_type>::new())?,
+            )
+        };
+    }
+    match op {
+        Identify::OP => Ok(match_op!(Identify, Identify)),
+        Reidentify::OP => Ok(match_op!(Reidentify, Reidentify)),
+        request::InfoPart::OP => Ok(match_op!(Request, request::InfoPart)),
+        request_batch::InfoPart::OP => Ok(match_op!(RequestBatch, request_batch::InfoPart)),
+        invalid => Err(de::Error::invalid_value(
+            de::Unexpected::Signed(invalid.into()),
+            &"valid OBS Client->Server message OpCode",
+        )),
+    }
+}
+
 struct MessageDataVisitor<Data> {
     _p: PhantomData<Data>,
 }
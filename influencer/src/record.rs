@@ -0,0 +1,481 @@
+use crate::auth::MessageStream;
+
+use base64ct::Encoding as _;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, ErrorKind as IoErrorKind, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+use tungstenite::{
+    Error as WsError, Message as WsMessage,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+/// Errors that might occur while loading or replaying a recording.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Reading the recording failed ({0})")]
+    Io(#[from] io::Error),
+    #[error("Invalid recording entry on line {line} ({source})")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+    /// A recorded binary payload is not valid base64.
+    #[error("Invalid base64 payload in recording")]
+    Base64,
+    /// The code being replayed to sent something other than what was recorded.
+    #[error("Sent {actual:?}, but the recording expected {expected:?}")]
+    Mismatch {
+        expected: Option<Frame>,
+        actual: Frame,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// A WebSocket message in a form that can be written as JSON.
+/// Binary payloads are base64-encoded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Frame {
+    Text {
+        data: String,
+    },
+    Binary {
+        data: String,
+    },
+    Ping {
+        data: String,
+    },
+    Pong {
+        data: String,
+    },
+    Close {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        code: Option<u16>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        reason: String,
+    },
+}
+impl Frame {
+    /// Returns `None` for raw frames, which are never returned by `read`.
+    pub fn from_message(message: &WsMessage) -> Option<Frame> {
+        let base64 = |data: &[u8]| base64ct::Base64::encode_string(data);
+        Some(match message {
+            WsMessage::Text(text) => Frame::Text {
+                data: text.as_str().to_owned(),
+            },
+            WsMessage::Binary(data) => Frame::Binary { data: base64(data) },
+            WsMessage::Ping(data) => Frame::Ping { data: base64(data) },
+            WsMessage::Pong(data) => Frame::Pong { data: base64(data) },
+            WsMessage::Close(frame) => Frame::Close {
+                code: frame.as_ref().map(|frame| frame.code.into()),
+                reason: frame
+                    .as_ref()
+                    .map(|frame| frame.reason.as_str().to_owned())
+                    .unwrap_or_default(),
+            },
+            WsMessage::Frame(_) => return None,
+        })
+    }
+    pub fn to_message(&self) -> Result<WsMessage, Error> {
+        let base64 = |data: &str| {
+            base64ct::Base64::decode_vec(data)
+                .map(Vec::into)
+                .map_err(|_| Error::Base64)
+        };
+        Ok(match self {
+            Frame::Text { data } => WsMessage::text(data.as_str()),
+            Frame::Binary { data } => WsMessage::Binary(base64(data)?),
+            Frame::Ping { data } => WsMessage::Ping(base64(data)?),
+            Frame::Pong { data } => WsMessage::Pong(base64(data)?),
+            Frame::Close { code, reason } => WsMessage::Close(code.map(|code| CloseFrame {
+                code: CloseCode::from(code),
+                reason: reason.as_str().into(),
+            })),
+        })
+    }
+    fn is_ping_or_pong(&self) -> bool {
+        matches!(self, Frame::Ping { .. } | Frame::Pong { .. })
+    }
+    /// Like `==`, but text frames containing equivalent JSON also match.
+    fn matches(&self, other: &Frame) -> bool {
+        if let (Frame::Text { data: a }, Frame::Text { data: b }) = (self, other)
+            && let Ok(a) = serde_json::from_str::<serde_json::Value>(a)
+            && let Ok(b) = serde_json::from_str::<serde_json::Value>(b)
+        {
+            return a == b;
+        }
+        self == other
+    }
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    pub direction: Direction,
+    #[serde(flatten)]
+    pub frame: Frame,
+}
+
+/// The line starting each session of a recording, like
+/// `{"time":1700000000000,"session":"start"}`.
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionStart {
+    time: u64,
+    session: SessionMarker,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SessionMarker {
+    Start,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis() as u64)
+}
+
+/// A [`MessageStream`] that writes every message read from or written to
+/// the stream it wraps to `out`, as JSON lines of [`Entry`].
+///
+/// The first message is preceded by a session marker, so that any number
+/// of recorders can append to the same file (one per connection, say) and
+/// [`Replay::sessions`] can tell them apart.
+#[derive(Debug)]
+pub struct Recorder<Stream, W> {
+    inner: Stream,
+    out: W,
+    started: bool,
+}
+impl<Stream: MessageStream, W: Write> Recorder<Stream, W> {
+    pub fn new(inner: Stream, out: W) -> Self {
+        Self {
+            inner,
+            out,
+            started: false,
+        }
+    }
+    pub fn get_ref(&self) -> &Stream {
+        &self.inner
+    }
+    /// Messages read or written through the returned reference are not recorded.
+    pub fn get_mut(&mut self) -> &mut Stream {
+        &mut self.inner
+    }
+    pub fn into_parts(self) -> (Stream, W) {
+        (self.inner, self.out)
+    }
+    fn record(&mut self, direction: Direction, message: &WsMessage) -> io::Result<()> {
+        let Some(frame) = Frame::from_message(message) else {
+            return Ok(());
+        };
+        let time = now();
+        let mut line = Vec::new();
+        if !self.started {
+            let start = SessionStart {
+                time,
+                session: SessionMarker::Start,
+            };
+            serde_json::to_writer(&mut line, &start)?;
+            line.push(b'\n');
+        }
+        let entry = Entry {
+            time,
+            direction,
+            frame,
+        };
+        serde_json::to_writer(&mut line, &entry)?;
+        line.push(b'\n');
+        // One write per entry, so that a crash doesn't leave half a line
+        self.out.write_all(&line)?;
+        self.out.flush()?;
+        self.started = true;
+        Ok(())
+    }
+}
+impl<Stream: MessageStream, W: Write> MessageStream for Recorder<Stream, W> {
+    fn read(&mut self) -> Result<WsMessage, WsError> {
+        let message = self.inner.read()?;
+        self.record(Direction::Received, &message)?;
+        Ok(message)
+    }
+    fn write(&mut self, message: WsMessage) -> Result<(), WsError> {
+        let entry = message.clone();
+        self.inner.write(message)?;
+        Ok(self.record(Direction::Sent, &entry)?)
+    }
+    fn flush(&mut self) -> Result<(), WsError> {
+        self.inner.flush()
+    }
+}
+
+/// A [`MessageStream`] that plays a recording back.
+///
+/// Reads return the recorded received messages in order. Writes are checked
+/// against the recorded sent messages, failing with an I/O error wrapping
+/// [`Error::Mismatch`] if they differ (text messages are compared as JSON
+/// if possible). Reading while the recording expects a message to be sent
+/// first fails with an I/O WouldBlock error, like a socket that has nothing
+/// to read yet. Pings and pongs sent by the code under test are ignored,
+/// since their timing is not deterministic. Once the recording is exhausted,
+/// reads return `ConnectionClosed`.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    entries: VecDeque<Entry>,
+}
+impl Replay {
+    pub fn new(entries: impl IntoIterator<Item = Entry>) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+        }
+    }
+    /// Loads the first session of a recording made by [`Recorder`].
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, Error> {
+        let sessions = Self::sessions(reader)?;
+        Ok(sessions.into_iter().next().unwrap_or_else(|| Self::new([])))
+    }
+    /// Loads every session of a recording made by [`Recorder`], in order.
+    /// Recordings without session markers are a single session.
+    pub fn sessions(reader: impl BufRead) -> Result<Vec<Self>, Error> {
+        let mut sessions = Vec::new();
+        let mut entries = VecDeque::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if serde_json::from_str::<SessionStart>(&line).is_ok() {
+                if !entries.is_empty() {
+                    sessions.push(Self {
+                        entries: std::mem::take(&mut entries),
+                    });
+                }
+                continue;
+            }
+            let entry = serde_json::from_str(&line).map_err(|source| Error::Json {
+                line: i + 1,
+                source,
+            })?;
+            entries.push_back(entry);
+        }
+        if !entries.is_empty() {
+            sessions.push(Self { entries });
+        }
+        Ok(sessions)
+    }
+    /// The entries that have not been replayed yet.
+    pub fn remaining(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+    /// Skips sent pings and pongs at the front of the recording.
+    fn skip_sent_pings(&mut self) {
+        while let Some(entry) = self.entries.front()
+            && entry.direction == Direction::Sent
+            && entry.frame.is_ping_or_pong()
+        {
+            self.entries.pop_front();
+        }
+    }
+}
+impl MessageStream for Replay {
+    fn read(&mut self) -> Result<WsMessage, WsError> {
+        self.skip_sent_pings();
+        match self.entries.front() {
+            None => Err(WsError::ConnectionClosed),
+            Some(entry) if entry.direction == Direction::Sent => {
+                Err(WsError::Io(IoErrorKind::WouldBlock.into()))
+            }
+            Some(_) => {
+                let entry = self.entries.pop_front().unwrap();
+                entry.frame.to_message().map_err(invalid_data)
+            }
+        }
+    }
+    fn write(&mut self, message: WsMessage) -> Result<(), WsError> {
+        let Some(actual) = Frame::from_message(&message) else {
+            return Ok(());
+        };
+        if actual.is_ping_or_pong() {
+            return Ok(());
+        }
+        self.skip_sent_pings();
+        match self.entries.front() {
+            Some(entry) if entry.direction == Direction::Sent && entry.frame.matches(&actual) => {
+                self.entries.pop_front();
+                Ok(())
+            }
+            entry => {
+                let expected = entry
+                    .filter(|entry| entry.direction == Direction::Sent)
+                    .map(|entry| entry.frame.clone());
+                Err(invalid_data(Error::Mismatch { expected, actual }))
+            }
+        }
+    }
+    fn flush(&mut self) -> Result<(), WsError> {
+        Ok(())
+    }
+}
+
+fn invalid_data(error: Error) -> WsError {
+    WsError::Io(io::Error::new(IoErrorKind::InvalidData, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, MemoryStream};
+
+    fn text(data: &str) -> WsMessage {
+        WsMessage::text(data)
+    }
+
+    /// Records a session in which `sent` is sent and `received` received.
+    fn record(out: Vec<u8>, sent: &str, received: &str) -> Vec<u8> {
+        let (a, mut b) = testing::duplex();
+        let mut recorder = Recorder::new(a, out);
+        recorder.write(text(sent)).unwrap();
+        b.write(text(received)).unwrap();
+        assert_eq!(recorder.read().unwrap(), text(received));
+        assert_eq!(b.read().unwrap(), text(sent));
+        recorder.into_parts().1
+    }
+
+    fn is_would_block(error: &WsError) -> bool {
+        matches!(error, WsError::Io(error) if error.kind() == IoErrorKind::WouldBlock)
+    }
+
+    #[test]
+    fn frames() {
+        let messages = [
+            text("{}"),
+            WsMessage::Binary(vec![0, 1, 255].into()),
+            WsMessage::Ping(b"ping".to_vec().into()),
+            WsMessage::Pong(Vec::new().into()),
+            WsMessage::Close(None),
+            WsMessage::Close(Some(CloseFrame {
+                code: CloseCode::from(4009),
+                reason: "Bye".into(),
+            })),
+        ];
+        for message in messages {
+            let frame = Frame::from_message(&message).unwrap();
+            assert_eq!(frame.to_message().unwrap(), message);
+        }
+        let binary = Frame::from_message(&WsMessage::Binary(vec![0, 1, 255].into()));
+        assert_eq!(
+            binary,
+            Some(Frame::Binary {
+                data: "AAH/".to_owned()
+            })
+        );
+        let invalid = Frame::Binary {
+            data: "!".to_owned(),
+        };
+        assert!(matches!(invalid.to_message(), Err(Error::Base64)));
+    }
+
+    #[test]
+    fn recording() {
+        let out = record(Vec::new(), r#"{"op":6}"#, r#"{"op":7}"#);
+        let lines: Vec<serde_json::Value> = out
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["session"], "start");
+        assert_eq!(lines[1]["direction"], "sent");
+        assert_eq!(lines[1]["type"], "text");
+        assert_eq!(lines[1]["data"], r#"{"op":6}"#);
+        assert_eq!(lines[2]["direction"], "received");
+        assert!(lines[2]["time"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn replay() {
+        let out = record(Vec::new(), r#"{"op":6,"d":{}}"#, r#"{"op":7}"#);
+        let mut replay = Replay::from_reader(out.as_slice()).unwrap();
+        // The recording expects something to be sent first
+        assert!(is_would_block(&replay.read().unwrap_err()));
+        // Pings are not deterministic, so they are ignored
+        replay.write(WsMessage::Ping(Vec::new().into())).unwrap();
+        // Equivalent JSON is good enough
+        replay.write(text(r#"{ "d": {}, "op": 6 }"#)).unwrap();
+        assert_eq!(replay.read().unwrap(), text(r#"{"op":7}"#));
+        assert!(matches!(replay.read(), Err(WsError::ConnectionClosed)));
+        assert_eq!(replay.remaining().count(), 0);
+    }
+
+    #[test]
+    fn replay_mismatch() {
+        let out = record(Vec::new(), r#"{"op":6}"#, r#"{"op":7}"#);
+        let mut replay = Replay::from_reader(out.as_slice()).unwrap();
+        let Err(WsError::Io(error)) = replay.write(text(r#"{"op":8}"#)) else {
+            panic!("mismatch not detected");
+        };
+        assert_eq!(error.kind(), IoErrorKind::InvalidData);
+        let error = error.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(
+            *error,
+            Error::Mismatch {
+                expected: Some(Frame::Text { .. }),
+                actual: Frame::Text { .. },
+            }
+        ));
+        // Nothing was consumed
+        assert_eq!(replay.remaining().count(), 2);
+    }
+
+    #[test]
+    fn sessions() {
+        // Appended to by two recorders, like a client that reconnected
+        let out = record(Vec::new(), r#"{"op":6}"#, r#"{"op":7}"#);
+        let out = record(out, r#"{"op":6,"d":2}"#, r#"{"op":7,"d":2}"#);
+        let sessions = Replay::sessions(out.as_slice()).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert!(sessions.iter().all(|s| s.remaining().count() == 2));
+        let mut first = Replay::from_reader(out.as_slice()).unwrap();
+        first.write(text(r#"{"op":6}"#)).unwrap();
+        first.read().unwrap();
+        assert!(matches!(first.read(), Err(WsError::ConnectionClosed)));
+
+        // Recordings without markers are a single session
+        let legacy = r#"{"time":1,"direction":"sent","type":"text","data":"a"}
+{"time":2,"direction":"received","type":"close"}
+"#;
+        let sessions = Replay::sessions(legacy.as_bytes()).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].remaining().count(), 2);
+        assert_eq!(Replay::sessions(&b""[..]).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn invalid_recording() {
+        let recording = "{\"time\":1,\"session\":\"start\"}\n\n{\"time\":2}\n";
+        let error = Replay::from_reader(recording.as_bytes()).unwrap_err();
+        assert!(matches!(error, Error::Json { line: 3, .. }), "{error}");
+    }
+
+    #[test]
+    fn unrecorded() {
+        let (a, mut b) = testing::duplex();
+        let mut recorder = Recorder::new(a, Vec::new());
+        recorder.get_mut().write(text("{}")).unwrap();
+        assert_eq!(b.read().unwrap(), text("{}"));
+        let (_, out): (MemoryStream, _) = recorder.into_parts();
+        assert!(out.is_empty());
+    }
+}
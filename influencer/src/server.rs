@@ -0,0 +1,251 @@
+use crate::{
+    auth::{self, MessageStream},
+    message::{
+        self as m, IntoWsMessageJson as _, WsMessageExt as _, close_code, event_subscription,
+    },
+};
+
+use base64ct::Encoding as _;
+use thiserror::Error;
+use tungstenite::{
+    Error as WsError,
+    protocol::{CloseFrame, frame::coding::CloseCode},
+};
+
+/// The highest RPC version this implementation speaks.
+pub const RPC_VERSION: u32 = 1;
+/// The `eventSubscriptions` value assumed if a client doesn't send one
+/// (all non-high-volume events), like OBS does.
+pub const DEFAULT_EVENT_SUBSCRIPTIONS: u32 = event_subscription::ALL;
+
+/// Errors that might occur while authenticating a client.
+#[derive(Debug, Error)]
+pub enum Error {
+    // Boxed because clippy is unhappy about how large tungstenite's errors are
+    /// An error coming from the underlying WebSocket connection.
+    #[error("Underlying WebSocket error ({0})")]
+    WebSocket(Box<WsError>),
+    /// The client sent something other than a valid `Identify` message.
+    #[error("Unexpected message ({0})")]
+    Decode(#[from] m::DecodeError),
+    /// The client's `authentication` string is missing or wrong.
+    #[error("Authentication failed")]
+    AuthenticationFailed,
+    /// The client asked for an RPC version that is not supported.
+    #[error("Unsupported RPC version ({0})")]
+    UnsupportedRpcVersion(u32),
+    /// The client sent a close frame instead of identifying.
+    #[error("Connection closed by peer ({})", auth::display_close_frame(.0))]
+    Closed(Option<CloseFrame>),
+}
+impl From<WsError> for Error {
+    fn from(value: WsError) -> Self {
+        Error::WebSocket(Box::new(value))
+    }
+}
+impl From<auth::Error> for Error {
+    fn from(value: auth::Error) -> Self {
+        match value {
+            auth::Error::WebSocket(error) => Error::WebSocket(error),
+            auth::Error::Decode(error) => Error::Decode(error),
+            auth::Error::Closed(frame) => Error::Closed(frame),
+        }
+    }
+}
+impl Error {
+    /// The close frame OBS would send in response to this error, if any.
+    pub fn close_frame(&self) -> Option<CloseFrame> {
+        let (code, reason) = match self {
+            Error::WebSocket(_) | Error::Closed(_) => return None,
+            Error::Decode(_) => (
+                close_code::MESSAGE_DECODE_ERROR,
+                "Invalid Identify message.",
+            ),
+            Error::AuthenticationFailed => {
+                (close_code::AUTHENTICATION_FAILED, "Authentication failed.")
+            }
+            Error::UnsupportedRpcVersion(_) => (
+                close_code::UNSUPPORTED_RPC_VERSION,
+                "Requested an unsupported RPC version.",
+            ),
+        };
+        Some(CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.into(),
+        })
+    }
+}
+
+/// What the server learned about a client while identifying it.
+#[derive(Debug, Clone, Copy)]
+pub struct Identified {
+    pub rpc_version: u32,
+    pub event_subscriptions: u32,
+}
+
+/// The server side of OBS authentication. Holds the password and
+/// a per-connection salt and challenge.
+#[derive(Debug, Clone)]
+pub struct ServerAuth {
+    password: Option<String>,
+    salt: String,
+    challenge: String,
+}
+impl ServerAuth {
+    /// Creates a [`ServerAuth`] with a random salt and challenge.
+    /// If there is no password, clients don't need to authenticate.
+    pub fn new(password: Option<&str>) -> Self {
+        Self::with_salt_and_challenge(password, &random_base64(), &random_base64())
+    }
+    /// Creates a [`ServerAuth`] with a fixed salt and challenge.
+    /// Only useful for reproducible tests.
+    pub fn with_salt_and_challenge(password: Option<&str>, salt: &str, challenge: &str) -> Self {
+        Self {
+            password: password.map(ToOwned::to_owned),
+            salt: salt.to_owned(),
+            challenge: challenge.to_owned(),
+        }
+    }
+    /// The `Hello` message to send to a newly connected client.
+    pub fn hello(&self) -> m::Hello<'_> {
+        m::Hello {
+            obs_web_socket_version: None,
+            rpc_version: Some(RPC_VERSION),
+            authentication: self.password.as_ref().map(|_| m::hello::Authentication {
+                challenge: &self.challenge,
+                salt: &self.salt,
+            }),
+        }
+    }
+    /// Checks a client's `Identify` message, and negotiates the RPC version.
+    pub fn verify(&self, identify: &m::Identify) -> Result<Identified, Error> {
+        if let Some(ref password) = self.password {
+            let expected = auth::authentication_string(password, &self.salt, &self.challenge);
+            let given = identify.authentication.unwrap_or("");
+            if !constant_time_eq(expected.as_bytes(), given.as_bytes()) {
+                return Err(Error::AuthenticationFailed);
+            }
+        }
+        if identify.rpc_version == 0 || identify.rpc_version > RPC_VERSION {
+            return Err(Error::UnsupportedRpcVersion(identify.rpc_version));
+        }
+        Ok(Identified {
+            rpc_version: identify.rpc_version,
+            event_subscriptions: identify
+                .event_subscriptions
+                .unwrap_or(DEFAULT_EVENT_SUBSCRIPTIONS),
+        })
+    }
+    /// Performs the whole handshake on a blocking stream: sends `Hello`,
+    /// reads and verifies `Identify`, then sends `Identified`.
+    /// On failure, the client is sent a close frame if appropriate.
+    pub fn handshake<Stream: MessageStream>(
+        &self,
+        stream: &mut Stream,
+    ) -> Result<Identified, Error> {
+        let hello = self
+            .hello()
+            .into_ws_message_json()
+            .map_err(m::DecodeError::from)?;
+        stream.write(hello)?;
+        stream.flush()?;
+        let message = stream.read_data()?;
+        let identified = message
+            .obs_message_data::<m::Identify>()
+            .map_err(Error::from)
+            .and_then(|identify| self.verify(&identify));
+        let identified = match identified {
+            Ok(identified) => identified,
+            Err(error) => {
+                if let Some(frame) = error.close_frame() {
+                    let _ = stream.close_handshake(Some(frame));
+                }
+                return Err(error);
+            }
+        };
+        let message = m::Identified {
+            negotiated_rpc_version: identified.rpc_version,
+        }
+        .into_ws_message_json()
+        .map_err(m::DecodeError::from)?;
+        stream.write(message)?;
+        stream.flush()?;
+        Ok(identified)
+    }
+}
+
+fn random_base64() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).expect("system random number generator failed");
+    base64ct::Base64::encode_string(&bytes)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identify(rpc_version: u32, authentication: Option<&str>) -> m::Identify<'_> {
+        m::Identify {
+            rpc_version,
+            authentication,
+            event_subscriptions: None,
+        }
+    }
+
+    #[test]
+    fn verify_password() {
+        let server = ServerAuth::with_salt_and_challenge(Some("p4ssw0rd"), "salt", "challenge");
+        let authentication = auth::authentication_string("p4ssw0rd", "salt", "challenge");
+        let identified = server.verify(&identify(1, Some(&authentication))).unwrap();
+        assert_eq!(identified.rpc_version, 1);
+        assert_eq!(identified.event_subscriptions, event_subscription::ALL);
+
+        let wrong = auth::authentication_string("wrong", "salt", "challenge");
+        for authentication in [Some(wrong.as_str()), Some(""), None] {
+            let error = server.verify(&identify(1, authentication)).unwrap_err();
+            assert!(matches!(error, Error::AuthenticationFailed));
+            let frame = error.close_frame().unwrap();
+            assert_eq!(u16::from(frame.code), close_code::AUTHENTICATION_FAILED);
+        }
+    }
+
+    #[test]
+    fn verify_without_password() {
+        let server = ServerAuth::new(None);
+        assert!(server.hello().authentication.is_none());
+        // Whatever the client sends is fine
+        server.verify(&identify(1, Some("anything"))).unwrap();
+        let mut identify = identify(1, None);
+        identify.event_subscriptions = Some(event_subscription::INPUT_VOLUME_METERS);
+        let identified = server.verify(&identify).unwrap();
+        assert_eq!(
+            identified.event_subscriptions,
+            event_subscription::INPUT_VOLUME_METERS
+        );
+    }
+
+    #[test]
+    fn rpc_version() {
+        let server = ServerAuth::new(None);
+        assert_eq!(server.hello().rpc_version, Some(RPC_VERSION));
+        for rpc_version in [0, RPC_VERSION + 1] {
+            let error = server.verify(&identify(rpc_version, None)).unwrap_err();
+            assert!(matches!(error, Error::UnsupportedRpcVersion(v) if v == rpc_version));
+            let frame = error.close_frame().unwrap();
+            assert_eq!(u16::from(frame.code), close_code::UNSUPPORTED_RPC_VERSION);
+        }
+    }
+
+    #[test]
+    fn constant_time_eq() {
+        assert!(super::constant_time_eq(b"", b""));
+        assert!(super::constant_time_eq(b"abc", b"abc"));
+        assert!(!super::constant_time_eq(b"abc", b"abd"));
+        assert!(!super::constant_time_eq(b"abc", b"ab"));
+        assert!(!super::constant_time_eq(b"", b"a"));
+    }
+}
@@ -0,0 +1,324 @@
+use crate::auth::MessageStream;
+
+use std::{
+    collections::VecDeque,
+    io::ErrorKind as IoErrorKind,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    time::Duration,
+};
+use tungstenite::{Error as WsError, Message as WsMessage, error::ProtocolError};
+
+#[derive(Debug, Default)]
+struct QueueState {
+    messages: VecDeque<WsMessage>,
+    /// The writing end is gone.
+    writer_dropped: bool,
+}
+
+/// Messages travelling in one direction.
+#[derive(Debug, Default)]
+struct Queue {
+    state: Mutex<QueueState>,
+    changed: Condvar,
+}
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    fn push(&self, message: WsMessage) {
+        self.lock().messages.push_back(message);
+        self.changed.notify_all();
+    }
+}
+
+/// How a [`MemoryStream`] behaves when there is nothing to read.
+#[derive(Debug, Clone, Copy)]
+pub enum ReadMode {
+    /// Fail with an I/O WouldBlock error right away, like a non-blocking socket.
+    NonBlocking,
+    /// Wait for a message, optionally failing with an I/O WouldBlock
+    /// error after a timeout, like a socket with a read timeout.
+    Blocking(Option<Duration>),
+}
+
+/// One end of an in-memory connection created by [`duplex`].
+///
+/// Messages written to one end can be read from the other. Like
+/// `tungstenite::WebSocket`, a stream answers pings with pongs and close
+/// frames with close frames, and reports `ConnectionClosed` once the closing
+/// handshake is complete.
+#[derive(Debug)]
+pub struct MemoryStream {
+    incoming: Arc<Queue>,
+    outgoing: Arc<Queue>,
+    read_mode: ReadMode,
+    would_block_reads: usize,
+    would_block_writes: usize,
+    sent_close: bool,
+    received_close: bool,
+}
+
+/// Creates two connected [`MemoryStream`]s, both in [`ReadMode::Blocking`]
+/// mode without a timeout.
+pub fn duplex() -> (MemoryStream, MemoryStream) {
+    let a = Arc::new(Queue::default());
+    let b = Arc::new(Queue::default());
+    (
+        MemoryStream::new(a.clone(), b.clone()),
+        MemoryStream::new(b, a),
+    )
+}
+
+impl MemoryStream {
+    fn new(incoming: Arc<Queue>, outgoing: Arc<Queue>) -> Self {
+        Self {
+            incoming,
+            outgoing,
+            read_mode: ReadMode::Blocking(None),
+            would_block_reads: 0,
+            would_block_writes: 0,
+            sent_close: false,
+            received_close: false,
+        }
+    }
+    pub fn set_read_mode(&mut self, mode: ReadMode) {
+        self.read_mode = mode;
+    }
+    /// Makes the next `n` reads fail with an I/O WouldBlock error,
+    /// regardless of whether messages are available.
+    pub fn inject_would_block_reads(&mut self, n: usize) {
+        self.would_block_reads = n;
+    }
+    /// Makes the next `n` writes fail with an I/O WouldBlock error.
+    /// The messages of failed writes are not sent.
+    pub fn inject_would_block_writes(&mut self, n: usize) {
+        self.would_block_writes = n;
+    }
+    /// Queues a message to be read from this end, as if the other end had
+    /// sent it. Useful for control frames, which are usually not
+    /// written explicitly.
+    pub fn inject(&self, message: WsMessage) {
+        self.incoming.push(message);
+    }
+    /// The number of messages waiting to be read from this end.
+    pub fn pending(&self) -> usize {
+        self.incoming.lock().messages.len()
+    }
+    fn would_block() -> WsError {
+        WsError::Io(IoErrorKind::WouldBlock.into())
+    }
+    #[allow(clippy::result_large_err)]
+    fn next_message(&mut self) -> Result<WsMessage, WsError> {
+        let mut state = self.incoming.lock();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                return Ok(message);
+            }
+            if state.writer_dropped {
+                return Err(WsError::Protocol(
+                    ProtocolError::ResetWithoutClosingHandshake,
+                ));
+            }
+            state = match self.read_mode {
+                ReadMode::NonBlocking => return Err(Self::would_block()),
+                ReadMode::Blocking(None) => self
+                    .incoming
+                    .changed
+                    .wait(state)
+                    .unwrap_or_else(|e| e.into_inner()),
+                ReadMode::Blocking(Some(timeout)) => {
+                    let (state, res) = self
+                        .incoming
+                        .changed
+                        .wait_timeout(state, timeout)
+                        .unwrap_or_else(|e| e.into_inner());
+                    if res.timed_out() && state.messages.is_empty() {
+                        return Err(Self::would_block());
+                    }
+                    state
+                }
+            };
+        }
+    }
+}
+
+impl MessageStream for MemoryStream {
+    fn read(&mut self) -> Result<WsMessage, WsError> {
+        if self.received_close {
+            return Err(WsError::ConnectionClosed);
+        }
+        if self.would_block_reads > 0 {
+            self.would_block_reads -= 1;
+            return Err(Self::would_block());
+        }
+        let message = self.next_message()?;
+        match message {
+            WsMessage::Ping(ref payload) if !self.sent_close => {
+                self.outgoing.push(WsMessage::Pong(payload.clone()));
+            }
+            WsMessage::Close(ref frame) => {
+                self.received_close = true;
+                // Like tungstenite, echo the frame unless this is the reply
+                // to our own, and report ConnectionClosed on the next read
+                if !self.sent_close {
+                    self.sent_close = true;
+                    self.outgoing.push(WsMessage::Close(frame.clone()));
+                }
+            }
+            _ => {}
+        }
+        Ok(message)
+    }
+    fn write(&mut self, message: WsMessage) -> Result<(), WsError> {
+        if self.would_block_writes > 0 {
+            self.would_block_writes -= 1;
+            return Err(Self::would_block());
+        }
+        if self.sent_close {
+            // Repeated close frames are ignored, like in tungstenite
+            return match message {
+                WsMessage::Close(_) => Ok(()),
+                _ => Err(WsError::AlreadyClosed),
+            };
+        }
+        if let WsMessage::Close(_) = message {
+            self.sent_close = true;
+        }
+        self.outgoing.push(message);
+        Ok(())
+    }
+    fn flush(&mut self) -> Result<(), WsError> {
+        Ok(())
+    }
+}
+
+impl Drop for MemoryStream {
+    fn drop(&mut self) {
+        self.outgoing.lock().writer_dropped = true;
+        self.outgoing.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{self, AuthMachine, DriveResult},
+        message::close_code,
+        server::{self, ServerAuth},
+    };
+
+    use std::thread;
+    use tungstenite::protocol::{CloseFrame, frame::coding::CloseCode};
+
+    fn serve(
+        password: Option<&'static str>,
+        mut stream: MemoryStream,
+    ) -> thread::JoinHandle<Result<server::Identified, server::Error>> {
+        thread::spawn(move || ServerAuth::new(password).handshake(&mut stream))
+    }
+
+    #[test]
+    fn auth() {
+        let (client, server) = duplex();
+        let server = serve(Some("p4ssw0rd"), server);
+        let machine = AuthMachine::new(client, Some("p4ssw0rd"), Some(5));
+        let (_, rpc_version) = machine.drive().ready().unwrap();
+        assert_eq!(rpc_version, 1);
+        let identified = server.join().unwrap().unwrap();
+        assert_eq!(identified.event_subscriptions, 5);
+    }
+
+    #[test]
+    fn auth_failure() {
+        let (client, server) = duplex();
+        let server = serve(Some("p4ssw0rd"), server);
+        let machine = AuthMachine::new(client, Some("wrong"), None);
+        let Err(auth::Error::Closed(Some(frame))) = machine.drive().ready() else {
+            panic!("expected a close frame");
+        };
+        assert_eq!(u16::from(frame.code), close_code::AUTHENTICATION_FAILED);
+        let error = server.join().unwrap().unwrap_err();
+        assert!(matches!(error, server::Error::AuthenticationFailed));
+    }
+
+    #[test]
+    fn auth_resumes_after_would_block() {
+        let (mut client, server) = duplex();
+        client.set_read_mode(ReadMode::NonBlocking);
+        client.inject_would_block_reads(2);
+        client.inject_would_block_writes(1);
+        let server = serve(None, server);
+        let mut machine = AuthMachine::new_non_blocking(client, None, None);
+        let mut interruptions = 0;
+        let rpc_version = loop {
+            machine = match machine.drive() {
+                DriveResult::Ready { rpc_version, .. } => break rpc_version,
+                DriveResult::Interrupted { cont, .. } => {
+                    interruptions += 1;
+                    thread::yield_now();
+                    cont
+                }
+                DriveResult::FatalError { error, .. } => panic!("{error}"),
+            };
+        };
+        assert_eq!(rpc_version, 1);
+        assert!(interruptions >= 3);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn read_timeout() {
+        let (mut a, _b) = duplex();
+        a.set_read_mode(ReadMode::Blocking(Some(Duration::from_millis(10))));
+        let Err(WsError::Io(error)) = a.read() else {
+            panic!("expected an I/O error");
+        };
+        assert_eq!(error.kind(), IoErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn ping() {
+        let (mut a, mut b) = duplex();
+        a.inject(WsMessage::Ping("hi".into()));
+        assert_eq!(a.pending(), 1);
+        assert!(matches!(a.read(), Ok(WsMessage::Ping(_))));
+        assert_eq!(b.read().unwrap(), WsMessage::Pong("hi".into()));
+    }
+
+    #[test]
+    fn close_handshake() {
+        let (mut a, mut b) = duplex();
+        let frame = CloseFrame {
+            code: CloseCode::Away,
+            reason: "bye".into(),
+        };
+        a.write(WsMessage::Close(Some(frame.clone()))).unwrap();
+        // The closing side sees the echo, then ConnectionClosed
+        assert_eq!(b.read().unwrap(), WsMessage::Close(Some(frame.clone())));
+        assert_eq!(a.read().unwrap(), WsMessage::Close(Some(frame)));
+        assert!(matches!(a.read(), Err(WsError::ConnectionClosed)));
+        assert!(matches!(b.read(), Err(WsError::ConnectionClosed)));
+        assert!(matches!(
+            b.write(WsMessage::text("x")),
+            Err(WsError::AlreadyClosed)
+        ));
+
+        let (mut a, mut b) = duplex();
+        let closer = thread::spawn(move || a.close_handshake(None).is_ok());
+        assert_eq!(b.read().unwrap(), WsMessage::Close(None));
+        assert!(closer.join().unwrap());
+    }
+
+    #[test]
+    fn dropped() {
+        let (mut a, b) = duplex();
+        drop(b);
+        assert!(matches!(
+            a.read(),
+            Err(WsError::Protocol(
+                ProtocolError::ResetWithoutClosingHandshake
+            ))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+use influencer::{
+    client::{Client, Config, Error, Keepalive},
+    message::{self as m, IntoWsMessageJson as _, WsMessageExt as _},
+};
+use influencer_mock::{MockServer, Script};
+use serde_json::{Value, json};
+use std::{
+    net::TcpListener,
+    time::{Duration, Instant},
+};
+
+fn config(server: &MockServer) -> Config {
+    let mut config = Config::new("127.0.0.1", server.port());
+    config.read_timeout = Some(Duration::from_millis(20));
+    config
+}
+
+/// Sends a request and returns the data of its response.
+fn request(client: &mut Client, request_type: &str) -> Result<Option<Value>, Error> {
+    client.send(
+        m::Request::<()> {
+            request_type,
+            request_id: "test",
+            request_data: None,
+        }
+        .into_ws_message_json()?,
+    )?;
+    let message = client.read()?;
+    let response = message.obs_message_data::<m::AnyResponse>().unwrap();
+    Ok(response.response_data)
+}
+
+#[test]
+fn keepalive() {
+    let server = MockServer::start(Script::new()).unwrap();
+    let mut config = config(&server);
+    config.keepalive = Some(Keepalive {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+    });
+    let mut client = Client::connect(config).unwrap();
+    // Pongs keep the connection alive for several intervals
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(500) {
+        assert!(client.try_read().unwrap().is_none());
+    }
+
+    server.set_frozen(true);
+    let start = Instant::now();
+    let error = loop {
+        match client.try_read() {
+            Ok(_) => assert!(start.elapsed() < Duration::from_secs(5)),
+            Err(error) => break error,
+        }
+    };
+    assert!(matches!(error, Error::KeepaliveTimeout(_)), "{error}");
+    assert!(error.is_disconnect());
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn reconnect() {
+    let version = json!({"rpcVersion": 1});
+    let script = Script::new().respond("GetVersion", Some(version.clone()));
+    let server = MockServer::start(script).unwrap();
+    let mut config = config(&server);
+    config.auto_reconnect = true;
+    let mut client = Client::connect(config).unwrap();
+    server.disconnect();
+    let start = Instant::now();
+    while server.identified() < 2 {
+        assert!(client.try_read().unwrap().is_none());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+    assert_eq!(request(&mut client, "GetVersion").unwrap(), Some(version));
+    assert_eq!(client.reconnects(), 1);
+}
+
+#[test]
+fn reconnect_refused() {
+    let server = MockServer::start(Script::new().password("p4ssw0rd")).unwrap();
+    let mut config = config(&server);
+    config.password = Some("p4ssw0rd".to_owned());
+    config.auto_reconnect = true;
+    let mut client = Client::connect(config).unwrap();
+    // A wrong password isn't retried, but returned
+    server.set_password(Some("hunter2"));
+    server.disconnect();
+    let start = Instant::now();
+    let error = loop {
+        match client.try_read() {
+            Ok(_) => assert!(start.elapsed() < Duration::from_secs(5)),
+            Err(error) => break error,
+        }
+    };
+    assert!(matches!(error, Error::Auth(_)), "{error}");
+    assert!(!error.is_disconnect());
+    assert_eq!(client.reconnects(), 0);
+}
+
+#[test]
+fn reconnect_attempts() {
+    let server = MockServer::start(Script::new()).unwrap();
+    let mut config = config(&server);
+    config.auto_reconnect = true;
+    config.reconnect_attempts = Some(2);
+    let mut client = Client::connect(config).unwrap();
+    // Nothing listens anymore
+    server.disconnect();
+    drop(server);
+    let start = Instant::now();
+    let error = loop {
+        match client.try_read() {
+            Ok(_) => assert!(start.elapsed() < Duration::from_secs(10)),
+            Err(error) => break error,
+        }
+    };
+    assert!(error.is_disconnect(), "{error}");
+    // Waited before both attempts: 500ms, then 1s
+    assert!(start.elapsed() >= Duration::from_millis(1500));
+    assert_eq!(client.reconnects(), 0);
+}
+
+#[test]
+fn no_reconnect() {
+    let server = MockServer::start(Script::new()).unwrap();
+    let mut client = Client::connect(config(&server)).unwrap();
+    server.disconnect();
+    let start = Instant::now();
+    let error = loop {
+        match client.try_read() {
+            Ok(_) => assert!(start.elapsed() < Duration::from_secs(5)),
+            Err(error) => break error,
+        }
+    };
+    assert!(error.is_disconnect(), "{error}");
+    assert_eq!(server.identified(), 1);
+}
+
+#[test]
+fn handshake_timeout() {
+    // Accepts connections, but never says anything
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    let mut config = Config::new("127.0.0.1", listener.local_addr().unwrap().port());
+    config.handshake_timeout = Duration::from_millis(200);
+    let start = Instant::now();
+    let error = Client::connect(config).unwrap_err();
+    assert!(error.is_disconnect(), "{error}");
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+/// Waits a little for the server to see `n` closing handshakes.
+fn assert_closed(server: &MockServer, n: usize) {
+    let start = Instant::now();
+    while server.closed() < n && start.elapsed() < Duration::from_secs(5) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(server.closed(), n);
+}
+
+#[test]
+fn close() {
+    let server = MockServer::start(Script::new()).unwrap();
+    let mut client = Client::connect(config(&server)).unwrap();
+    client.close(None).unwrap();
+    assert_closed(&server, 1);
+    // Already closed, so dropping does nothing more
+    drop(client);
+    assert_closed(&server, 1);
+}
+
+#[test]
+fn close_on_drop() {
+    let server = MockServer::start(Script::new()).unwrap();
+    let mut config = config(&server);
+    drop(Client::connect(config.clone()).unwrap());
+    // Dropping just cuts the connection
+    std::thread::sleep(Duration::from_millis(100));
+    assert_eq!(server.closed(), 0);
+
+    config.close_on_drop = true;
+    drop(Client::connect(config).unwrap());
+    assert_closed(&server, 1);
+}
+
+#[test]
+fn close_timeout() {
+    let server = MockServer::start(Script::new().ignore_close()).unwrap();
+    let mut client = Client::connect(config(&server)).unwrap();
+    let start = Instant::now();
+    assert!(client.close(None).is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(server.closed(), 0);
+}
+
+#[test]
+fn url() {
+    let mut config = Config::new("::1", 4455);
+    assert_eq!(config.url(), "ws://[::1]:4455/");
+    config.host = "obs.lan".to_owned();
+    config.port = 443;
+    config.path = "/obs?x=1".to_owned();
+    assert_eq!(config.url(), "ws://obs.lan:443/obs?x=1");
+}